@@ -0,0 +1,995 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::error::AppError;
+use crate::filter::{parse_filter, FilterExpr};
+use crate::util::{glob_match, parse_size};
+use crate::walk::EntryKind;
+
+#[derive(Debug, Default)]
+pub struct Config {
+    pub root: PathBuf,
+    /// 指定された全ルート。`root` は常に先頭の要素と一致する
+    pub roots: Vec<PathBuf>,
+    /// 現在のルートに適用する深さ制限 (walk が参照する)
+    pub max_depth: Option<usize>,
+    /// `--max-depth 2,3,1` のルートごとの深さ制限 (位置対応)
+    pub max_depths: Vec<usize>,
+    pub max_total_size: Option<u64>,
+    pub sort: SortKey,
+    pub format: Format,
+    pub dir_sort: Option<SortKey>,
+    pub file_sort: Option<SortKey>,
+    pub hyperlinks: bool,
+    pub dirs_first: bool,
+    pub repo: bool,
+    pub filter: Option<FilterExpr>,
+    pub min_depth: Option<usize>,
+    pub min_depth_flat: bool,
+    pub collapse_dirs: Vec<String>,
+    pub error_summary: bool,
+    pub unique_names: bool,
+    pub report_empty_dirs: bool,
+    pub count_by_depth_json: bool,
+    pub show_root_stats: bool,
+    pub partition_by_size: bool,
+    pub trailing_slash: bool,
+    pub resolve_mounts: bool,
+    pub entry_template: Option<String>,
+    pub du: bool,
+    pub total_only_bytes: bool,
+    pub follow_only_dirs: bool,
+    pub silent_loops: bool,
+    pub include_target_metadata: bool,
+    pub show_size: bool,
+    pub show_time: bool,
+    pub time_kind: TimeKind,
+    pub deterministic_timestamps: bool,
+    /// 相対時刻表示の基準。SOURCE_DATE_EPOCH またはルートの mtime
+    pub time_reference: Option<SystemTime>,
+    pub sort_nulls: SortNulls,
+    pub sort_case: SortCase,
+    pub root_label: Option<String>,
+    pub max_siblings: Option<usize>,
+    pub exclude_larger_subtree: Option<usize>,
+    pub progress_json: bool,
+    pub seed: Option<u64>,
+    pub depth_indicator: bool,
+    pub compact: bool,
+    pub emit_root_error_as_tree: bool,
+    pub merge_roots: bool,
+    pub no_dereference_root: bool,
+    pub profile: bool,
+    pub show_link_count_summary: bool,
+    pub deduplicate_output: bool,
+    pub on_error: OnError,
+    pub print_json_schema: bool,
+    pub max_open_dirs: Option<usize>,
+    pub show_branch: bool,
+    pub pager: bool,
+    pub normalize_unicode: Option<UnicodeForm>,
+    pub mount_info: bool,
+    pub verify_utf8: bool,
+    pub files_before_subdirs: bool,
+    pub trim_common_prefix: bool,
+    pub color_depth: bool,
+    pub fail_on_empty: bool,
+    pub max_depth_auto: bool,
+    pub quiet_permission: bool,
+    pub stable_across_locale: bool,
+    pub xattr: bool,
+    pub collapse_files: bool,
+    pub git_root: bool,
+    pub line_numbers: bool,
+    pub summary_json: bool,
+    pub line_prefix: Option<String>,
+    pub no_indent: bool,
+    pub flat_sort: bool,
+    pub on_symlink: Option<SymlinkPolicy>,
+    pub report_deepest: bool,
+    pub skip_names: HashSet<String>,
+    pub wrap_root_in_object: bool,
+    pub fold_extensions: Option<usize>,
+    pub color_legend: bool,
+    pub only_files: bool,
+    pub entry_separator: Option<String>,
+    pub file_max_depth: Option<usize>,
+    pub fast: bool,
+    pub relative_targets: bool,
+    pub count_hidden_separately: bool,
+    pub allow_file: bool,
+    pub exec_cmd: Option<Vec<String>>,
+    pub exec_batch: bool,
+    pub escape_control: bool,
+    pub max_cols: Option<usize>,
+    pub width: Option<usize>,
+    pub output_buffer_size: Option<usize>,
+    pub limit_output_bytes: Option<u64>,
+    pub encode_names: Option<NameEncoding>,
+    pub entry_types: Option<Vec<EntryKind>>,
+    pub newer_than: Option<PathBuf>,
+    pub older_than: Option<PathBuf>,
+    pub newer_than_time: Option<SystemTime>,
+    pub older_than_time: Option<SystemTime>,
+    pub color: ColorMode,
+    pub color_active: bool,
+    pub strip_on_redirect: bool,
+    pub force_color: bool,
+    pub ignore_patterns: Vec<String>,
+    pub match_patterns: Vec<String>,
+    pub all: bool,
+    pub dry_run_filters: bool,
+    pub git_status: HashMap<PathBuf, char>,
+    pub repo_root: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorMode {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+pub fn parse_color_mode(s: &str) -> Result<ColorMode, AppError> {
+    match s {
+        "auto" => Ok(ColorMode::Auto),
+        "always" => Ok(ColorMode::Always),
+        "never" => Ok(ColorMode::Never),
+        _ => Err(AppError::InvalidArgs),
+    }
+}
+
+/// 実際に色を出すかどうかを出力先とフラグから決める。
+/// `--color=always` でもリダイレクト先がファイルなら `--strip-on-redirect` で
+/// 抑止できる (`--force-color` はそれより優先)
+pub fn effective_color(config: &Config, is_tty: bool) -> bool {
+    match config.color {
+        ColorMode::Never => false,
+        ColorMode::Auto => is_tty,
+        ColorMode::Always => !(config.strip_on_redirect && !is_tty && !config.force_color),
+    }
+}
+
+/// 折り返しや整列に使う画面幅を決める。`--width` の指定が最優先で、
+/// 端末に出しているなら COLUMNS、どちらも取れなければ 80 に退避する
+pub fn effective_width(config: &Config, is_tty: bool) -> usize {
+    if let Some(width) = config.width {
+        return width;
+    }
+    if is_tty
+        && let Some(cols) = std::env::var("COLUMNS").ok().and_then(|v| v.parse().ok())
+    {
+        return cols;
+    }
+    80
+}
+
+/// `--normalize-unicode` の正規化形式。NFD で保存されるファイル名 (macOS) と
+/// NFC で入力されるパターンの不一致を吸収する
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnicodeForm {
+    Nfc,
+    Nfd,
+}
+
+pub fn parse_unicode_form(s: &str) -> Result<UnicodeForm, AppError> {
+    match s {
+        "nfc" => Ok(UnicodeForm::Nfc),
+        "nfd" => Ok(UnicodeForm::Nfd),
+        _ => Err(AppError::InvalidArgs),
+    }
+}
+
+/// 走査中にエントリ単位で起きたエラーへの方針 (`--on-error`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnError {
+    /// 何も出力せずに続行する
+    Skip,
+    /// 続行しつつ stderr に警告を出す
+    #[default]
+    Warn,
+    /// 最初のエラーで走査を打ち切る
+    Abort,
+}
+
+pub fn parse_on_error(s: &str) -> Result<OnError, AppError> {
+    match s {
+        "skip" => Ok(OnError::Skip),
+        "warn" => Ok(OnError::Warn),
+        "abort" => Ok(OnError::Abort),
+        _ => Err(AppError::InvalidArgs),
+    }
+}
+
+/// シンボリックリンクの統一的な扱い (`--on-symlink`)。未指定なら
+/// 従来どおり `-L` 系の個別フラグに従う
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// リンク自身を `-> target` 付きで表示し、辿らない
+    Show,
+    /// デリファレンスする (ディレクトリへのリンクはループ検出付きで降下)
+    Follow,
+    /// リンクを出力から完全に省く
+    Skip,
+}
+
+pub fn parse_symlink_policy(s: &str) -> Result<SymlinkPolicy, AppError> {
+    match s {
+        "show" => Ok(SymlinkPolicy::Show),
+        "follow" => Ok(SymlinkPolicy::Follow),
+        "skip" => Ok(SymlinkPolicy::Skip),
+        _ => Err(AppError::InvalidArgs),
+    }
+}
+
+/// `--time` が表示するタイムスタンプの種類 (`--time-kind`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimeKind {
+    #[default]
+    Modified,
+    Accessed,
+    Created,
+}
+
+pub fn parse_time_kind(s: &str) -> Result<TimeKind, AppError> {
+    match s {
+        "modified" => Ok(TimeKind::Modified),
+        "accessed" => Ok(TimeKind::Accessed),
+        "created" => Ok(TimeKind::Created),
+        _ => Err(AppError::InvalidArgs),
+    }
+}
+
+/// 出力フォーマット (`--format`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+    Yaml,
+    Xml,
+    Jsonl,
+}
+
+pub fn parse_format(s: &str) -> Result<Format, AppError> {
+    match s {
+        "text" => Ok(Format::Text),
+        "json" => Ok(Format::Json),
+        "yaml" => Ok(Format::Yaml),
+        "xml" => Ok(Format::Xml),
+        "jsonl" => Ok(Format::Jsonl),
+        _ => Err(AppError::InvalidArgs),
+    }
+}
+
+/// `--encode-names` で使うファイル名のエンコード方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameEncoding {
+    Base64,
+    Base32,
+}
+
+pub fn parse_name_encoding(s: &str) -> Result<NameEncoding, AppError> {
+    match s {
+        "base64" => Ok(NameEncoding::Base64),
+        "base32" => Ok(NameEncoding::Base32),
+        _ => Err(AppError::InvalidArgs),
+    }
+}
+
+/// ソート比較時の大文字小文字の扱い (`--sort-case`)。フィルタの
+/// `--ignore-case` とは独立している
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortCase {
+    #[default]
+    Fold,
+    Strict,
+}
+
+pub fn parse_sort_case(s: &str) -> Result<SortCase, AppError> {
+    match s {
+        "fold" => Ok(SortCase::Fold),
+        "strict" => Ok(SortCase::Strict),
+        _ => Err(AppError::InvalidArgs),
+    }
+}
+
+/// メタデータが取れなかったエントリをソートのどちら側に寄せるか
+/// (`--sort-nulls`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortNulls {
+    First,
+    #[default]
+    Last,
+}
+
+pub fn parse_sort_nulls(s: &str) -> Result<SortNulls, AppError> {
+    match s {
+        "first" => Ok(SortNulls::First),
+        "last" => Ok(SortNulls::Last),
+        _ => Err(AppError::InvalidArgs),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortKey {
+    #[default]
+    Name,
+    Count,
+    Random,
+    Size,
+    Mode,
+    FirstSeen,
+    NamePath,
+    NaturalCi,
+    ChildrenSize,
+    /// ディレクトリ、シンボリックリンク、ファイルの三段グループ (各グループ内は名前順)
+    TypeThenName,
+}
+
+/// `--seed-file` 用: ファイルにあるシードを読む。なければ現在時刻から
+/// 1 つ生成して書き込み、以後の実行が同じシャッフルを再現できるようにする
+fn load_or_create_seed(path: &Path) -> Result<u64, AppError> {
+    match fs::read_to_string(path) {
+        Ok(contents) => contents.trim().parse().map_err(|_| AppError::InvalidArgs),
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            let seed = SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64;
+            fs::write(path, seed.to_string())?;
+            Ok(seed)
+        }
+        Err(e) => Err(AppError::Io(e)),
+    }
+}
+
+pub fn parse_sort_key(s: &str) -> Result<SortKey, AppError> {
+    match s {
+        "name" => Ok(SortKey::Name),
+        "count" => Ok(SortKey::Count),
+        "random" => Ok(SortKey::Random),
+        "size" => Ok(SortKey::Size),
+        "mode" => Ok(SortKey::Mode),
+        "first-seen" => Ok(SortKey::FirstSeen),
+        "name-path" => Ok(SortKey::NamePath),
+        "natural-ci" => Ok(SortKey::NaturalCi),
+        "children-size" => Ok(SortKey::ChildrenSize),
+        "type-then-name" => Ok(SortKey::TypeThenName),
+        _ => Err(AppError::InvalidArgs),
+    }
+}
+
+/// `--type` の `f`/`d`/`l` の組み合わせをエントリ種別の集合に変換する
+pub fn parse_type_filter(s: &str) -> Result<Vec<EntryKind>, AppError> {
+    if s.is_empty() {
+        return Err(AppError::InvalidArgs);
+    }
+    s.chars()
+        .map(|c| match c {
+            'f' => Ok(EntryKind::File),
+            'd' => Ok(EntryKind::Dir),
+            'l' => Ok(EntryKind::Symlink),
+            _ => Err(AppError::InvalidArgs),
+        })
+        .collect()
+}
+
+/// `--entry-template` で使えるプレースホルダ
+const TEMPLATE_KEYS: [&str; 7] = ["name", "path", "size", "mtime", "type", "depth", "indent"];
+
+/// テンプレート内の `{...}` が既知のプレースホルダだけか検証する
+fn validate_template(template: &str) -> Result<(), AppError> {
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '{' {
+            continue;
+        }
+        let mut key = String::new();
+        let mut closed = false;
+        for k in chars.by_ref() {
+            if k == '}' {
+                closed = true;
+                break;
+            }
+            key.push(k);
+        }
+        if !closed || !TEMPLATE_KEYS.contains(&key.as_str()) {
+            return Err(AppError::InvalidArgs);
+        }
+    }
+    Ok(())
+}
+
+/// `--ignore-vcs` で除外するバージョン管理メタデータのディレクトリ
+const VCS_DIRS: [&str; 5] = [".git", ".svn", ".hg", ".bzr", "CVS"];
+
+pub fn parse_args(args: &[String]) -> Result<Config, AppError> {
+    let mut config = Config::default();
+    let mut roots: Vec<PathBuf> = Vec::new();
+
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--max-depth" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.max_depths = value
+                    .split(',')
+                    .map(|part| part.trim().parse().map_err(|_| AppError::InvalidArgs))
+                    .collect::<Result<_, _>>()?;
+                if config.max_depths.is_empty() {
+                    return Err(AppError::InvalidArgs);
+                }
+            }
+            "--max-total-size" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.max_total_size = Some(parse_size(value)?);
+            }
+            "--sort" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.sort = parse_sort_key(value)?;
+            }
+            _ if arg.starts_with("--sort=") => {
+                config.sort = parse_sort_key(&arg["--sort=".len()..])?;
+            }
+            "--sort-case" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.sort_case = parse_sort_case(value)?;
+            }
+            _ if arg.starts_with("--sort-case=") => {
+                config.sort_case = parse_sort_case(&arg["--sort-case=".len()..])?;
+            }
+            "--sort-nulls" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.sort_nulls = parse_sort_nulls(value)?;
+            }
+            _ if arg.starts_with("--sort-nulls=") => {
+                config.sort_nulls = parse_sort_nulls(&arg["--sort-nulls=".len()..])?;
+            }
+            "--dir-sort" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.dir_sort = Some(parse_sort_key(value)?);
+            }
+            "--file-sort" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.file_sort = Some(parse_sort_key(value)?);
+            }
+            "--hyperlinks" => config.hyperlinks = true,
+            "--color" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.color = parse_color_mode(value)?;
+            }
+            _ if arg.starts_with("--color=") => {
+                config.color = parse_color_mode(&arg["--color=".len()..])?;
+            }
+            "--strip-on-redirect" => config.strip_on_redirect = true,
+            "--force-color" => config.force_color = true,
+            "--repo" => config.repo = true,
+            "--filter" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.filter = Some(parse_filter(value)?);
+            }
+            "--min-depth" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.min_depth = Some(value.parse().map_err(|_| AppError::InvalidArgs)?);
+            }
+            "--min-depth-flat" => config.min_depth_flat = true,
+            "--error-summary" => config.error_summary = true,
+            "--unique-names" => config.unique_names = true,
+            "--report-empty-dirs" => config.report_empty_dirs = true,
+            "--count-by-depth-json" => config.count_by_depth_json = true,
+            "--show-root-stats" => config.show_root_stats = true,
+            "--partition-by-size" => config.partition_by_size = true,
+            "--trailing-slash" => config.trailing_slash = true,
+            "--resolve-mounts" => config.resolve_mounts = true,
+            "--du" => config.du = true,
+            "--total-only-bytes" => config.total_only_bytes = true,
+            "--follow-only-dirs" => config.follow_only_dirs = true,
+            "--silent-loops" => config.silent_loops = true,
+            "--report-loops" => config.silent_loops = false,
+            "--include-target-metadata" => config.include_target_metadata = true,
+            "--size" => config.show_size = true,
+            "--all" | "-a" => config.all = true,
+            "--dry-run-filters" => config.dry_run_filters = true,
+            "--ignore-vcs" => config
+                .ignore_patterns
+                .extend(VCS_DIRS.iter().map(|d| d.to_string())),
+            "-I" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.ignore_patterns.push(value.clone());
+            }
+            "--time" => config.show_time = true,
+            "--deterministic-timestamps" => config.deterministic_timestamps = true,
+            "--time-kind" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.time_kind = parse_time_kind(value)?;
+            }
+            _ if arg.starts_with("--time-kind=") => {
+                config.time_kind = parse_time_kind(&arg["--time-kind=".len()..])?;
+            }
+            "--progress-json" => config.progress_json = true,
+            "--depth-indicator" => config.depth_indicator = true,
+            "--compact" => config.compact = true,
+            "--emit-root-error-as-tree" => config.emit_root_error_as_tree = true,
+            "--merge-roots" => config.merge_roots = true,
+            "--no-dereference-root" => config.no_dereference_root = true,
+            "--profile" => config.profile = true,
+            "--show-link-count-summary" => config.show_link_count_summary = true,
+            "--deduplicate-output" => config.deduplicate_output = true,
+            "--print-json-schema" => config.print_json_schema = true,
+            "--show-branch" => config.show_branch = true,
+            "--pager" => config.pager = true,
+            "--mount-info" => config.mount_info = true,
+            "--verify-utf8" => config.verify_utf8 = true,
+            "--files-before-subdirs" => config.files_before_subdirs = true,
+            "--trim-common-prefix" => config.trim_common_prefix = true,
+            "--color-depth" => config.color_depth = true,
+            "--fail-on-empty" => config.fail_on_empty = true,
+            "--max-depth-auto" => config.max_depth_auto = true,
+            "--quiet-permission" => config.quiet_permission = true,
+            // ソートは常にコードポイント順でロケール非依存。明示用に受け付ける
+            "--stable-across-locale" => config.stable_across_locale = true,
+            "--xattr" => config.xattr = true,
+            "--collapse-files" => config.collapse_files = true,
+            "--git-root" => config.git_root = true,
+            "--line-numbers" => config.line_numbers = true,
+            "--summary-json" => config.summary_json = true,
+            "--no-indent" => config.no_indent = true,
+            "--only-files" => config.only_files = true,
+            "--fast" => config.fast = true,
+            "--relative-targets" => config.relative_targets = true,
+            "--count-hidden-separately" => config.count_hidden_separately = true,
+            "--allow-file" => config.allow_file = true,
+            "--entry-separator" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.entry_separator = Some(value.clone());
+            }
+            "--flat-sort" => config.flat_sort = true,
+            "--report-deepest" => config.report_deepest = true,
+            "--wrap-root-in-object" => config.wrap_root_in_object = true,
+            "--color-legend" => config.color_legend = true,
+            "--file-max-depth" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.file_max_depth = Some(value.parse().map_err(|_| AppError::InvalidArgs)?);
+            }
+            "--fold-extensions" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.fold_extensions = Some(value.parse().map_err(|_| AppError::InvalidArgs)?);
+            }
+            "--skip-names" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                let contents = fs::read_to_string(value)?;
+                config.skip_names.extend(
+                    contents
+                        .lines()
+                        .map(str::trim)
+                        .filter(|l| !l.is_empty())
+                        .map(str::to_string),
+                );
+            }
+            "--prefix" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.line_prefix = Some(value.clone());
+            }
+            "-P" | "--pattern" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.match_patterns.push(value.clone());
+            }
+            "--normalize-unicode" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.normalize_unicode = Some(parse_unicode_form(value)?);
+            }
+            _ if arg.starts_with("--normalize-unicode=") => {
+                config.normalize_unicode =
+                    Some(parse_unicode_form(&arg["--normalize-unicode=".len()..])?);
+            }
+            "--max-open-dirs" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                let limit: usize = value.parse().map_err(|_| AppError::InvalidArgs)?;
+                // 0 は取得が永遠に待つことになるため弾く
+                if limit == 0 {
+                    return Err(AppError::InvalidArgs);
+                }
+                config.max_open_dirs = Some(limit);
+            }
+            "--on-symlink" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.on_symlink = Some(parse_symlink_policy(value)?);
+            }
+            _ if arg.starts_with("--on-symlink=") => {
+                config.on_symlink = Some(parse_symlink_policy(&arg["--on-symlink=".len()..])?);
+            }
+            "--on-error" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.on_error = parse_on_error(value)?;
+            }
+            _ if arg.starts_with("--on-error=") => {
+                config.on_error = parse_on_error(&arg["--on-error=".len()..])?;
+            }
+            "--width" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.width = Some(value.parse().map_err(|_| AppError::InvalidArgs)?);
+            }
+            "--exec" => {
+                // find -exec と同様に `;` を終端とする (終端省略も許す)。
+                // `+` で終わる形はパスをまとめて 1 回の起動に渡す
+                let mut cmd = Vec::new();
+                for value in iter.by_ref() {
+                    if value == ";" {
+                        break;
+                    }
+                    if value == "+" {
+                        config.exec_batch = true;
+                        break;
+                    }
+                    cmd.push(value.clone());
+                }
+                if cmd.is_empty() {
+                    return Err(AppError::InvalidArgs);
+                }
+                config.exec_cmd = Some(cmd);
+            }
+            "--escape-control" => config.escape_control = true,
+            "--format" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.format = parse_format(value)?;
+            }
+            _ if arg.starts_with("--format=") => {
+                config.format = parse_format(&arg["--format=".len()..])?;
+            }
+            "--encode-names" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.encode_names = Some(parse_name_encoding(value)?);
+            }
+            "--limit-output-bytes" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.limit_output_bytes = Some(parse_size(value)?);
+            }
+            "--output-buffer-size" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.output_buffer_size = Some(parse_size(value)? as usize);
+            }
+            "--max-cols" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.max_cols = Some(value.parse().map_err(|_| AppError::InvalidArgs)?);
+            }
+            "--seed" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.seed = Some(value.parse().map_err(|_| AppError::InvalidArgs)?);
+            }
+            "--seed-file" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.seed = Some(load_or_create_seed(Path::new(value))?);
+            }
+            "--type" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.entry_types = Some(parse_type_filter(value)?);
+            }
+            "--newer-than" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.newer_than = Some(PathBuf::from(value));
+            }
+            "--older-than" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.older_than = Some(PathBuf::from(value));
+            }
+            "--exclude-larger-subtree" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.exclude_larger_subtree =
+                    Some(value.parse().map_err(|_| AppError::InvalidArgs)?);
+            }
+            "--max-siblings" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.max_siblings = Some(value.parse().map_err(|_| AppError::InvalidArgs)?);
+            }
+            "--entry-template" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                validate_template(value)?;
+                config.entry_template = Some(value.clone());
+            }
+            "--root-label" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.root_label = Some(value.clone());
+            }
+            "--collapse-dir" => {
+                let value = iter.next().ok_or(AppError::InvalidArgs)?;
+                config.collapse_dirs.push(value.clone());
+            }
+            _ if arg.starts_with('-') => return Err(AppError::InvalidArgs),
+            _ => roots.push(PathBuf::from(arg)),
+        }
+    }
+
+    if roots.is_empty() {
+        // スキーマ出力はルートなしで完結するため、便宜上 "." を補う
+        if !config.print_json_schema {
+            return Err(AppError::InvalidArgs);
+        }
+        roots.push(PathBuf::from("."));
+    }
+    // 複数指定された深さ制限はルートと位置で対応するため数が合わない場合は弾く
+    if config.max_depths.len() > 1 && config.max_depths.len() != roots.len() {
+        return Err(AppError::InvalidArgs);
+    }
+    config.root = roots[0].clone();
+    config.roots = roots;
+    Ok(config)
+}
+
+impl Config {
+    pub fn is_ignored(&self, path: &Path, name: &str, is_dir: bool) -> bool {
+        self.ignored_by(path, name, is_dir).is_some()
+    }
+
+    /// エントリにマッチした ignore パターンを返す。`--dry-run-filters` の
+    /// 除外理由表示でどのパターンが効いたかを示すのに使う
+    pub fn ignored_by(&self, path: &Path, name: &str, is_dir: bool) -> Option<&str> {
+        let name = self.normalize(name);
+        self.ignore_patterns.iter().map(String::as_str).find(|raw| {
+            let (pattern, dir_only) = match raw.strip_suffix('/') {
+                Some(p) => (p, true),
+                None => (*raw, false),
+            };
+            let pattern = self.normalize(pattern);
+            if dir_only && !is_dir {
+                return false;
+            }
+            match pattern.strip_prefix('/') {
+                Some(anchored) => {
+                    if let Some(root) = &self.repo_root {
+                        path.strip_prefix(root)
+                            .map(|rel| {
+                                glob_match(anchored, &self.normalize(&rel.to_string_lossy()))
+                            })
+                            .unwrap_or(false)
+                    } else {
+                        false
+                    }
+                }
+                None => glob_match(&pattern, &name),
+            }
+        })
+    }
+
+    /// `--normalize-unicode` 指定時にファイル名とパターンを同じ形式へそろえる
+    pub fn normalize(&self, s: &str) -> String {
+        use unicode_normalization::UnicodeNormalization;
+
+        match self.normalize_unicode {
+            Some(UnicodeForm::Nfc) => s.nfc().collect(),
+            Some(UnicodeForm::Nfd) => s.nfd().collect(),
+            None => s.to_string(),
+        }
+    }
+
+    pub fn status_note(&self, path: &Path) -> Option<String> {
+        self.git_status.get(path).map(|c| format!("[{}]", c))
+    }
+
+    /// `--newer-than`/`--older-than` の基準ファイルの mtime を解決する
+    pub fn resolve_time_filters(&mut self) -> Result<(), AppError> {
+        self.newer_than_time = self.newer_than.as_deref().map(reference_mtime).transpose()?;
+        self.older_than_time = self.older_than.as_deref().map(reference_mtime).transpose()?;
+        Ok(())
+    }
+}
+
+fn reference_mtime(path: &Path) -> Result<SystemTime, AppError> {
+    let metadata = fs::metadata(path).map_err(|e| match e.kind() {
+        ErrorKind::NotFound => AppError::PathNotFound(path.to_path_buf()),
+        _ => AppError::Io(e),
+    })?;
+    metadata.modified().map_err(AppError::from)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_args_user_input_none_returns_err() {
+        let args = vec!["treer".to_string()];
+
+        assert!(matches!(parse_args(&args), Err(AppError::InvalidArgs)));
+    }
+
+    #[test]
+    fn parse_args_user_input_unknown_flag_returns_err() {
+        let args = vec!["treer".to_string(), "-z".to_string(), ".".to_string()];
+
+        assert!(matches!(parse_args(&args), Err(AppError::InvalidArgs)));
+    }
+
+    #[test]
+    fn parse_args_user_input_one_returns_ok() {
+        let args = vec!["treer".to_string(), ".".to_string()];
+
+        let config = parse_args(&args).unwrap();
+        assert_eq!(config.root, PathBuf::from("."));
+    }
+
+    #[test]
+    fn parse_args_max_total_size_returns_ok() {
+        let args = vec![
+            "treer".to_string(),
+            "--max-total-size".to_string(),
+            "1K".to_string(),
+            ".".to_string(),
+        ];
+
+        let config = parse_args(&args).unwrap();
+        assert_eq!(config.max_total_size, Some(1024));
+    }
+
+    #[test]
+    fn parse_args_max_total_size_missing_value_returns_err() {
+        let args = vec!["treer".to_string(), "--max-total-size".to_string()];
+
+        assert!(matches!(parse_args(&args), Err(AppError::InvalidArgs)));
+    }
+
+    #[test]
+    fn parse_args_sort_count_returns_ok() {
+        let args = vec!["treer".to_string(), "--sort=count".to_string(), ".".to_string()];
+
+        let config = parse_args(&args).unwrap();
+        assert_eq!(config.sort, SortKey::Count);
+    }
+
+    #[test]
+    fn parse_args_sort_unknown_key_returns_err() {
+        let args = vec!["treer".to_string(), "--sort=foo".to_string(), ".".to_string()];
+
+        assert!(matches!(parse_args(&args), Err(AppError::InvalidArgs)));
+    }
+
+    #[test]
+    fn parse_type_filter_combined_types() {
+        let types = parse_type_filter("fl").unwrap();
+        assert_eq!(types, vec![EntryKind::File, EntryKind::Symlink]);
+    }
+
+    #[test]
+    fn parse_type_filter_unknown_char_returns_err() {
+        assert!(matches!(parse_type_filter("x"), Err(AppError::InvalidArgs)));
+        assert!(matches!(parse_type_filter(""), Err(AppError::InvalidArgs)));
+    }
+
+    #[test]
+    fn parse_args_multiple_roots_with_per_root_depths() {
+        let args: Vec<String> = ["treer", "--max-depth", "1,2", "a", "b"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        let config = parse_args(&args).unwrap();
+        assert_eq!(config.roots, vec![PathBuf::from("a"), PathBuf::from("b")]);
+        assert_eq!(config.root, PathBuf::from("a"));
+        assert_eq!(config.max_depths, vec![1, 2]);
+    }
+
+    #[test]
+    fn parse_args_depth_count_mismatch_returns_err() {
+        let args: Vec<String> = ["treer", "--max-depth", "1,2,3", "a", "b"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        assert!(matches!(parse_args(&args), Err(AppError::InvalidArgs)));
+    }
+
+    #[test]
+    fn parse_args_entry_template_unknown_placeholder_returns_err() {
+        let args: Vec<String> = ["treer", "--entry-template", "{name} {owner}", "."]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        assert!(matches!(parse_args(&args), Err(AppError::InvalidArgs)));
+
+        let args: Vec<String> = ["treer", "--entry-template", "{indent}{name} {size}", "."]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert!(parse_args(&args).is_ok());
+    }
+
+    #[test]
+    fn resolve_time_filters_missing_reference_returns_err() {
+        let mut config = Config {
+            newer_than: Some(PathBuf::from("/no/such/reference")),
+            ..Config::default()
+        };
+
+        assert!(matches!(
+            config.resolve_time_filters(),
+            Err(AppError::PathNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn effective_color_strip_on_redirect_overrides_always() {
+        let config = Config {
+            color: ColorMode::Always,
+            strip_on_redirect: true,
+            ..Config::default()
+        };
+
+        assert!(!effective_color(&config, false));
+        assert!(effective_color(&config, true));
+    }
+
+    #[test]
+    fn effective_color_force_color_wins_over_strip() {
+        let config = Config {
+            color: ColorMode::Always,
+            strip_on_redirect: true,
+            force_color: true,
+            ..Config::default()
+        };
+
+        assert!(effective_color(&config, false));
+    }
+
+    #[test]
+    fn effective_width_prefers_override_then_default() {
+        let mut config = Config {
+            width: Some(120),
+            ..Config::default()
+        };
+        assert_eq!(effective_width(&config, false), 120);
+
+        // 端末でなければ COLUMNS は見ずに既定の 80 を返す
+        config.width = None;
+        assert_eq!(effective_width(&config, false), 80);
+    }
+
+    #[test]
+    fn effective_color_auto_follows_tty() {
+        let config = Config::default();
+
+        assert!(effective_color(&config, true));
+        assert!(!effective_color(&config, false));
+    }
+
+    #[test]
+    fn normalize_unicode_matches_nfd_name_against_nfc_pattern() {
+        // "é" の NFD 表現 (e + 結合アクセント) と NFC 表現 (単一コードポイント)
+        let nfd_name = "cafe\u{301}.txt";
+        let config = Config {
+            ignore_patterns: vec!["caf\u{e9}.txt".to_string()],
+            normalize_unicode: Some(UnicodeForm::Nfc),
+            ..Config::default()
+        };
+        assert!(config.is_ignored(Path::new(nfd_name), nfd_name, false));
+
+        // 正規化なしでは一致しない
+        let config = Config {
+            ignore_patterns: vec!["caf\u{e9}.txt".to_string()],
+            ..Config::default()
+        };
+        assert!(!config.is_ignored(Path::new(nfd_name), nfd_name, false));
+    }
+
+    #[test]
+    fn parse_args_seed_file_persists_and_reuses_seed() {
+        let dir = tempfile::tempdir().unwrap();
+        let seed_path = dir.path().join("seed");
+        let args = vec![
+            "treer".to_string(),
+            "--seed-file".to_string(),
+            seed_path.display().to_string(),
+            ".".to_string(),
+        ];
+
+        let first = parse_args(&args).unwrap();
+        assert!(seed_path.exists());
+        let second = parse_args(&args).unwrap();
+        assert_eq!(first.seed, second.seed);
+        assert!(first.seed.is_some());
+    }
+}
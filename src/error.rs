@@ -0,0 +1,44 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum AppError {
+    InvalidArgs,
+    PathNotFound(PathBuf),
+    NotADirectory(PathBuf),
+    PermissionDenied(PathBuf),
+    /// `--verify-utf8` で見つかった UTF-8 として不正な名前のパス一覧
+    InvalidUtf8(Vec<PathBuf>),
+    /// `--git-root` をリポジトリ外で使った
+    NotInRepository(PathBuf),
+    Io(io::Error),
+}
+
+impl From<io::Error> for AppError {
+    fn from(e: io::Error) -> Self {
+        AppError::Io(e)
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::InvalidArgs => write!(f, "invalid arguments"),
+            AppError::PathNotFound(path) => write!(f, "path not found: {}", path.display()),
+            AppError::NotADirectory(path) => write!(f, "not a directory: {}", path.display()),
+            AppError::PermissionDenied(path) => write!(f, "permission denied: {}", path.display()),
+            AppError::NotInRepository(path) => {
+                write!(f, "not inside a git repository: {}", path.display())
+            }
+            AppError::InvalidUtf8(paths) => {
+                writeln!(f, "{} entries with non-UTF8 names:", paths.len())?;
+                for path in paths {
+                    writeln!(f, "  {}", path.display())?;
+                }
+                Ok(())
+            }
+            AppError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
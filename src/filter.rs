@@ -0,0 +1,228 @@
+use std::fs;
+use std::path::Path;
+
+use crate::error::AppError;
+use crate::util::parse_size;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterField {
+    Size,
+    Name,
+    Ext,
+    Type,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Gt,
+    Lt,
+    Ge,
+    Le,
+}
+
+/// `--filter` の式 (`size>1M && ext==rs` など) をパースした構文木
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterExpr {
+    And(Box<FilterExpr>, Box<FilterExpr>),
+    Or(Box<FilterExpr>, Box<FilterExpr>),
+    Cmp {
+        field: FilterField,
+        op: CmpOp,
+        value: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FilterToken {
+    Ident(String),
+    Op(CmpOp),
+    And,
+    Or,
+}
+
+fn tokenize_filter(input: &str) -> Result<Vec<FilterToken>, AppError> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = input.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            c if c.is_whitespace() => i += 1,
+            '&' | '|' => {
+                if i + 1 >= chars.len() || chars[i + 1] != c {
+                    return Err(AppError::InvalidArgs);
+                }
+                tokens.push(if c == '&' {
+                    FilterToken::And
+                } else {
+                    FilterToken::Or
+                });
+                i += 2;
+            }
+            '=' | '!' | '>' | '<' => {
+                let eq = i + 1 < chars.len() && chars[i + 1] == '=';
+                let op = match (c, eq) {
+                    ('=', true) => CmpOp::Eq,
+                    ('!', true) => CmpOp::Ne,
+                    ('>', true) => CmpOp::Ge,
+                    ('<', true) => CmpOp::Le,
+                    ('>', false) => CmpOp::Gt,
+                    ('<', false) => CmpOp::Lt,
+                    _ => return Err(AppError::InvalidArgs),
+                };
+                tokens.push(FilterToken::Op(op));
+                i += if eq { 2 } else { 1 };
+            }
+            _ => {
+                let start = i;
+                while i < chars.len()
+                    && !chars[i].is_whitespace()
+                    && !matches!(chars[i], '&' | '|' | '=' | '!' | '>' | '<')
+                {
+                    i += 1;
+                }
+                tokens.push(FilterToken::Ident(chars[start..i].iter().collect()));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// `||` < `&&` < 比較、の優先順位で再帰下降パースする
+pub fn parse_filter(input: &str) -> Result<FilterExpr, AppError> {
+    let tokens = tokenize_filter(input)?;
+    let mut pos = 0;
+    let expr = parse_filter_or(&tokens, &mut pos)?;
+    if pos != tokens.len() {
+        return Err(AppError::InvalidArgs);
+    }
+    Ok(expr)
+}
+
+fn parse_filter_or(tokens: &[FilterToken], pos: &mut usize) -> Result<FilterExpr, AppError> {
+    let mut left = parse_filter_and(tokens, pos)?;
+    while tokens.get(*pos) == Some(&FilterToken::Or) {
+        *pos += 1;
+        let right = parse_filter_and(tokens, pos)?;
+        left = FilterExpr::Or(Box::new(left), Box::new(right));
+    }
+    Ok(left)
+}
+
+fn parse_filter_and(tokens: &[FilterToken], pos: &mut usize) -> Result<FilterExpr, AppError> {
+    let mut left = parse_filter_cmp(tokens, pos)?;
+    while tokens.get(*pos) == Some(&FilterToken::And) {
+        *pos += 1;
+        let right = parse_filter_cmp(tokens, pos)?;
+        left = FilterExpr::And(Box::new(left), Box::new(right));
+    }
+    Ok(left)
+}
+
+fn parse_filter_cmp(tokens: &[FilterToken], pos: &mut usize) -> Result<FilterExpr, AppError> {
+    let Some(FilterToken::Ident(field)) = tokens.get(*pos) else {
+        return Err(AppError::InvalidArgs);
+    };
+    let field = match field.as_str() {
+        "size" => FilterField::Size,
+        "name" => FilterField::Name,
+        "ext" => FilterField::Ext,
+        "type" => FilterField::Type,
+        _ => return Err(AppError::InvalidArgs),
+    };
+    let Some(FilterToken::Op(op)) = tokens.get(*pos + 1) else {
+        return Err(AppError::InvalidArgs);
+    };
+    let Some(FilterToken::Ident(value)) = tokens.get(*pos + 2) else {
+        return Err(AppError::InvalidArgs);
+    };
+    *pos += 3;
+
+    Ok(FilterExpr::Cmp {
+        field,
+        op: *op,
+        value: value.clone(),
+    })
+}
+
+pub fn eval_filter(expr: &FilterExpr, name: &str, metadata: &fs::Metadata) -> bool {
+    match expr {
+        FilterExpr::And(l, r) => {
+            eval_filter(l, name, metadata) && eval_filter(r, name, metadata)
+        }
+        FilterExpr::Or(l, r) => eval_filter(l, name, metadata) || eval_filter(r, name, metadata),
+        FilterExpr::Cmp { field, op, value } => match field {
+            FilterField::Size => {
+                let Ok(rhs) = parse_size(value) else {
+                    return false;
+                };
+                cmp_matches(*op, metadata.len().cmp(&rhs))
+            }
+            FilterField::Name => cmp_matches(*op, name.cmp(value.as_str())),
+            FilterField::Ext => {
+                let ext = Path::new(name)
+                    .extension()
+                    .map(|e| e.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                cmp_matches(*op, ext.as_str().cmp(value.as_str()))
+            }
+            FilterField::Type => {
+                let kind = if metadata.is_dir() { "d" } else { "f" };
+                cmp_matches(*op, kind.cmp(value.as_str()))
+            }
+        },
+    }
+}
+
+fn cmp_matches(op: CmpOp, ordering: std::cmp::Ordering) -> bool {
+    use std::cmp::Ordering::*;
+    match op {
+        CmpOp::Eq => ordering == Equal,
+        CmpOp::Ne => ordering != Equal,
+        CmpOp::Gt => ordering == Greater,
+        CmpOp::Lt => ordering == Less,
+        CmpOp::Ge => ordering != Less,
+        CmpOp::Le => ordering != Greater,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_filter_compound_expression() {
+        let expr = parse_filter("size>1M && ext==rs").unwrap();
+
+        let FilterExpr::And(left, right) = expr else {
+            panic!("expected And, got {:?}", expr);
+        };
+        assert_eq!(
+            *left,
+            FilterExpr::Cmp {
+                field: FilterField::Size,
+                op: CmpOp::Gt,
+                value: "1M".to_string(),
+            }
+        );
+        assert_eq!(
+            *right,
+            FilterExpr::Cmp {
+                field: FilterField::Ext,
+                op: CmpOp::Eq,
+                value: "rs".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_filter_invalid_expression_returns_err() {
+        assert!(matches!(parse_filter("size >"), Err(AppError::InvalidArgs)));
+        assert!(matches!(parse_filter("owner==me"), Err(AppError::InvalidArgs)));
+        assert!(matches!(parse_filter("size>1 &"), Err(AppError::InvalidArgs)));
+    }
+}
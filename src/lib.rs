@@ -0,0 +1,15 @@
+//! Rust製 tree風CLI のライブラリ部分。
+//! CLI (`main.rs`) からも、走査だけ使いたい外部コードからも利用できる。
+
+pub mod config;
+pub mod error;
+pub mod filter;
+pub mod render;
+pub mod repo;
+pub mod sort;
+pub mod stats;
+pub mod util;
+pub mod walk;
+
+pub use error::AppError;
+pub use stats::{walk_stats, WalkStats};
@@ -1,198 +1,403 @@
 use std::env;
-use std::fmt;
-use std::fs;
-use std::io::{self, ErrorKind};
-use std::path::{Path, PathBuf};
-
-#[derive(Debug)]
-enum AppError {
-    InvalidArgs,
-    PathNotFound(PathBuf),
-    NotADirectory(PathBuf),
-    PermissionDenied(PathBuf),
-    Io(io::Error),
-}
+use std::time::Instant;
+use std::io::{self, BufWriter, IsTerminal, Write};
+
+use treer::config::{effective_color, effective_width, parse_args, Config, Format, SortKey, TimeKind};
+use treer::error::AppError;
+use treer::render::{
+    color_legend, json_schema, render_flat, render_json, render_json_value, render_jsonl, render_to_string, render_xml, render_yaml,
+    LimitedWriter,
+};
+use treer::repo::{apply_git_root, apply_repo_mode};
+use treer::sort::sort_tree;
+use treer::util::{common_dir_prefix, format_profile, spawn_pager};
+use treer::stats::{
+    aggregate_sizes, count_by_depth, duplicate_name_groups, empty_dirs, format_count_by_depth_json,
+    format_duplicate_names, format_empty_dirs, format_link_summary, format_size_partition,
+    format_summary_json, format_summary_with_hidden, deepest_path, format_deepest,
+    link_summary, partition_by_size,
+};
+use treer::walk::{
+    auto_max_depth, collapse_files, collapse_large_subtrees, collect_at_min_depth,
+    deduplicate_subtrees, exec_batched, exec_per_entry, file_count, fold_extensions,
+    format_error_summary, merge_roots, prune_files_below, prune_min_depth, prune_types,
+    root_error_node, truncate_siblings, validate_path, validate_path_no_follow, walk,
+    WalkOutcome, AUTO_DEPTH_BUDGET,
+};
 
-impl From<io::Error> for AppError {
-    fn from(e: io::Error) -> Self {
-        AppError::Io(e)
-    }
-}
+fn run() -> Result<(), AppError> {
+    let args: Vec<String> = env::args().collect();
+    let mut config = parse_args(&args)?;
 
-impl fmt::Display for AppError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            AppError::InvalidArgs => write!(f, "invalid arguments"),
-            AppError::PathNotFound(path) => write!(f, "path not found: {}", path.display()),
-            AppError::NotADirectory(path) => write!(f, "not a directory: {}", path.display()),
-            AppError::PermissionDenied(path) => write!(f, "permission denied: {}", path.display()),
-            AppError::Io(e) => write!(f, "I/O error: {}", e),
-        }
+    // スキーマ出力は走査せずに完結する
+    if config.print_json_schema {
+        println!("{}", json_schema(&config));
+        return Ok(());
     }
-}
 
-fn validate_path<P: AsRef<Path>>(path: P) -> Result<(), AppError> {
-    let path_ref = path.as_ref();
-
-    let metadata = fs::metadata(path_ref).map_err(|e| match e.kind() {
-        ErrorKind::NotFound => AppError::PathNotFound(path_ref.to_path_buf()),
-        _ => AppError::Io(e),
-    })?;
+    let is_tty = io::stdout().is_terminal();
+    // ハイパーリンクは端末に出力するときだけ有効にする
+    if config.hyperlinks && !is_tty {
+        config.hyperlinks = false;
+    }
+    // 端末への出力では制御文字のエスケープを既定で有効にする
+    if is_tty {
+        config.escape_control = true;
+    }
+    // --max-cols 未指定なら --width / 端末幅 (COLUMNS) を既定にする
+    if config.max_cols.is_none() && (config.width.is_some() || is_tty) {
+        config.max_cols = Some(effective_width(&config, is_tty));
+    }
+    config.color_active = effective_color(&config, is_tty);
 
-    if !metadata.is_dir() {
-        return Err(AppError::NotADirectory(path_ref.to_path_buf()));
+    // --git-root はフィルタ類を適用する前にルートを付け替える
+    if config.git_root {
+        apply_git_root(&mut config)?;
     }
 
-    Ok(())
-}
+    if config.repo {
+        apply_repo_mode(&mut config);
+    }
 
-fn read_directory<P: AsRef<Path>>(path: P) -> Result<Vec<fs::DirEntry>, AppError> {
-    let path_ref = path.as_ref();
-    fs::read_dir(path_ref)
-        .map_err(|e| match e.kind() {
-            ErrorKind::PermissionDenied => AppError::PermissionDenied(path_ref.to_path_buf()),
-            _ => AppError::Io(e),
-        })?
-        .map(|res| {
-            res.map_err(AppError::from)
-        })
-        .collect()
-}
+    // 非 Unix ではパーミッションが取れないため名前順に退避する
+    #[cfg(not(unix))]
+    if config.sort == SortKey::Mode {
+        eprintln!("warning: --sort=mode is unavailable on this platform, using name");
+        config.sort = SortKey::Name;
+    }
 
-fn parse_args(args: &[String]) -> Result<PathBuf, AppError> {
-    let count = args.len();
-    match count {
-        2 => Ok(PathBuf::from(&args[1])),
-        _ => Err(AppError::InvalidArgs),
+    // 作成時刻が取れない環境では mtime に退避する旨を知らせる
+    if config.time_kind == TimeKind::Created
+        && std::fs::metadata(&config.root)
+            .map(|m| m.created().is_err())
+            .unwrap_or(false)
+    {
+        eprintln!("warning: creation time is unavailable on this platform, using mtime");
     }
-}
 
-fn run() -> Result<(), AppError> {
-    let args: Vec<String> = env::args().collect();
-    let path = parse_args(&args)?;
+    // --seed 未指定の --sort=random は時刻ベースの seed を使う
+    if config.sort == SortKey::Random && config.seed.is_none() {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+        config.seed = Some(u64::from(nanos));
+    }
 
-    validate_path(&path)?;
-    let entries = read_directory(&path)?;
+    config.resolve_time_filters()?;
+    // 相対時刻の基準は SOURCE_DATE_EPOCH、なければ先頭ルートの mtime
+    if config.deterministic_timestamps {
+        config.time_reference = env::var("SOURCE_DATE_EPOCH")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .map(|secs| std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+            .or_else(|| {
+                std::fs::metadata(&config.root)
+                    .and_then(|m| m.modified())
+                    .ok()
+            });
+    }
 
-    for entry in entries {
-        println!("{}", entry.file_name().to_string_lossy());
+    // --pager は端末に出すときだけ起動し、以後の出力をその stdin に流す
+    let mut pager = if config.pager && is_tty {
+        let command = env::var("PAGER").unwrap_or_else(|_| "less -R".to_string());
+        spawn_pager(&command)
+    } else {
+        None
+    };
+
+    let stdout = io::stdout();
+    let sink: Box<dyn Write> = match pager.as_mut().and_then(|child| child.stdin.take()) {
+        Some(stdin) => Box::new(stdin),
+        None => Box::new(stdout.lock()),
+    };
+    // エントリごとの write で都度フラッシュされないよう、まとめて書き出す
+    let capacity = config.output_buffer_size.unwrap_or(64 * 1024);
+    let limit = config.limit_output_bytes.unwrap_or(u64::MAX);
+    let mut out = LimitedWriter::new(BufWriter::with_capacity(capacity, sink), limit);
+
+    let result = run_roots(&mut config, &mut out);
+    let truncated = out.truncated();
+    // ページャを待つ前にパイプを閉じる
+    drop(out);
+    if let Some(mut child) = pager {
+        let _ = child.wait();
+    }
+    let printed = match result {
+        // ページャが先に終了した場合は正常終了として扱う
+        Err(AppError::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+        other => other?,
+    };
+    // --fail-on-empty: フィルタ後に何も出なかったら grep 同様に 1 で終わる
+    if config.fail_on_empty && printed == 0 {
+        std::process::exit(1);
     }
 
+    if truncated {
+        eprintln!(
+            "warning: output truncated at {} bytes",
+            config.limit_output_bytes.unwrap_or_default()
+        );
+    }
     Ok(())
 }
 
-fn main() {
-    if let Err(e) = run() {
-        eprintln!("{}", e);
+/// ルートごとに走査と描画を行う。--max-depth は位置でルートに対応する
+/// 戻り値はフィルタ適用後に出力したファイル数 (--fail-on-empty の判定用)
+fn run_roots<W: Write>(config: &mut Config, out: &mut W) -> Result<usize, AppError> {
+    let roots = config.roots.clone();
+    let mut printed = 0;
+    // --trim-common-prefix: 共通の親はヘッダに 1 回だけ出し、ラベルから省く
+    let common_prefix = if config.trim_common_prefix && roots.len() > 1 {
+        common_dir_prefix(&roots)
+    } else {
+        None
+    };
+    if let Some(prefix) = &common_prefix {
+        writeln!(out, "[common prefix: {}]", prefix.display())?;
+    }
+    let user_label = config.root_label.clone();
+    // --wrap-root-in-object: ルート数によらず JSON を配列に統一する
+    let wrap_json = config.wrap_root_in_object && config.format == Format::Json;
+    if wrap_json {
+        write!(out, "[").map_err(AppError::Io)?;
     }
+    if config.merge_roots {
+        // 各ルートを仮想親 <roots> の子にまとめて 1 本のツリーとして扱う
+        let mut trees = Vec::new();
+        let mut errors = Vec::new();
+        let mut stat_calls = 0;
+        for (i, root) in roots.iter().enumerate() {
+            set_current_root(config, root, i);
+            apply_trimmed_label(config, root, common_prefix.as_deref(), user_label.as_deref());
+            let outcome = walk_root(config)?;
+            trees.push(outcome.root);
+            errors.extend(outcome.errors);
+            stat_calls += outcome.stat_calls;
+        }
+        let outcome = WalkOutcome {
+            root: merge_roots(trees),
+            errors,
+            stat_calls,
+        };
+        printed += process_outcome(config, outcome, out)?;
+    } else {
+        for (i, root) in roots.iter().enumerate() {
+            set_current_root(config, root, i);
+            apply_trimmed_label(config, root, common_prefix.as_deref(), user_label.as_deref());
+            if wrap_json && i > 0 {
+                write!(out, ",").map_err(AppError::Io)?;
+            }
+            let outcome = walk_root(config)?;
+            printed += process_outcome(config, outcome, out)?;
+        }
+    }
+    if wrap_json {
+        writeln!(out, "]").map_err(AppError::Io)?;
+    }
+    out.flush().map_err(AppError::Io)?;
+    Ok(printed)
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use std::fs::{self, File};
-    use tempfile::NamedTempFile;
-    use tempfile::tempdir;
-
-    #[test]
-    fn parse_args_user_input_none_returns_err() {
-        let args = vec!["treer".to_string()];
+/// 共通接頭辞を取り除いたルートラベルを設定する。--root-label 指定が優先
+fn apply_trimmed_label(
+    config: &mut Config,
+    root: &std::path::Path,
+    prefix: Option<&std::path::Path>,
+    user_label: Option<&str>,
+) {
+    config.root_label = match (user_label, prefix) {
+        (Some(label), _) => Some(label.to_string()),
+        (None, Some(prefix)) => {
+            let trimmed = root.strip_prefix(prefix).unwrap_or(root);
+            Some(if trimmed.as_os_str().is_empty() {
+                ".".to_string()
+            } else {
+                trimmed.display().to_string()
+            })
+        }
+        (None, None) => None,
+    };
+}
 
-        assert!(matches!(parse_args(&args), Err(AppError::InvalidArgs)));
+fn set_current_root(config: &mut Config, root: &std::path::Path, index: usize) {
+    config.root = root.to_path_buf();
+    config.max_depth = match config.max_depths.len() {
+        0 => None,
+        1 => Some(config.max_depths[0]),
+        _ => Some(config.max_depths[index]),
+    };
+    // --max-depth-auto: 明示的な制限がないルートにだけ自動の深さを選ぶ
+    if config.max_depth_auto && config.max_depth.is_none() {
+        let depth = auto_max_depth(&config.root, AUTO_DEPTH_BUDGET);
+        eprintln!("auto max depth: {}", depth);
+        config.max_depth = Some(depth);
     }
+}
 
-    #[test]
-    fn parse_args_user_input_multiple_returns_err() {
-        let args = vec!["treer".to_string(), "-a".to_string(), ".".to_string()];
-
-        assert!(matches!(parse_args(&args), Err(AppError::InvalidArgs)));
+fn walk_root(config: &Config) -> Result<WalkOutcome, AppError> {
+    let started = Instant::now();
+    let validation = if config.no_dereference_root {
+        validate_path_no_follow(&config.root)
+    } else {
+        validate_path(&config.root)
+    };
+    let walked = match validation {
+        // --allow-file: ファイルのルートはそのまま 1 ノードとして歩く
+        Err(AppError::NotADirectory(_)) if config.allow_file => walk(config),
+        other => other.and_then(|_| walk(config)),
+    };
+    let result = match walked {
+        Ok(outcome) => Ok(outcome),
+        // 失敗したルートもエラーノードとして出力に残す
+        Err(e) if config.emit_root_error_as_tree => Ok(WalkOutcome {
+            root: root_error_node(&config.root, &e),
+            errors: Vec::new(),
+            stat_calls: 0,
+        }),
+        Err(e) => Err(e),
+    };
+    if config.profile {
+        eprintln!("{}", format_profile("walk", started.elapsed()));
     }
+    result
+}
 
-    #[test]
-    fn parse_args_user_input_one_returns_ok() {
-        let args = vec!["treer".to_string(), ".".to_string()];
-
-        let path = parse_args(&args).unwrap();
-        assert_eq!(path, PathBuf::from("."));
+fn process_outcome<W: Write>(
+    config: &Config,
+    outcome: WalkOutcome,
+    out: &mut W,
+) -> Result<usize, AppError> {
+    let mut tree = outcome.root;
+    if let Some(types) = &config.entry_types {
+        prune_types(&mut tree, types);
     }
-
-    #[test]
-    fn validate_path_existing_directory_returns_ok() {
-        let temp_dir = tempdir().unwrap();
-        let path = temp_dir.path();
-
-        let result = validate_path(path);
-        assert!(result.is_ok());
+    if let Some(max) = config.exclude_larger_subtree {
+        collapse_large_subtrees(&mut tree, max);
     }
-
-    #[test]
-    fn validate_path_nonexistent_path_returns_err() {
-        let temp_dir = tempdir().unwrap();
-        let non_existent_path = temp_dir.path().join("foo");
-
-        let result = validate_path(non_existent_path);
-        assert!(matches!(result, Err(AppError::PathNotFound(_))));
+    // --sort=children-size は --du と同じ集約結果をキーに使う
+    if config.du || config.total_only_bytes || config.sort == SortKey::ChildrenSize {
+        aggregate_sizes(&mut tree);
+    }
+    let started = Instant::now();
+    sort_tree(&mut tree, config);
+    if config.profile {
+        eprintln!("{}", format_profile("sort", started.elapsed()));
+    }
+    if let Some(max) = config.max_siblings {
+        truncate_siblings(&mut tree, max);
+    }
+    if config.deduplicate_output {
+        deduplicate_subtrees(&mut tree);
+    }
+    if config.collapse_files {
+        collapse_files(&mut tree);
+    }
+    if let Some(threshold) = config.fold_extensions {
+        fold_extensions(&mut tree, threshold);
+    }
+    if let Some(max) = config.file_max_depth {
+        prune_files_below(&mut tree, max, 0);
     }
 
-    #[test]
-    fn validate_path_file_returns_err() {
-        let temp_file = NamedTempFile::new().unwrap();
-        let path = temp_file.path().to_path_buf();
+    // スクリプト向け: 合計バイト数の裸の整数だけを出して終わる
+    if config.total_only_bytes {
+        writeln!(out, "{}", tree.size.unwrap_or_default())?;
+        return Ok(file_count(&tree));
+    }
+    if let Some(min) = config.min_depth {
+        if config.min_depth_flat {
+            let paths = collect_at_min_depth(&tree, min);
+            for path in &paths {
+                writeln!(out, "{}", path)?;
+            }
+            return Ok(paths.len());
+        }
+        prune_min_depth(&mut tree, min);
+    }
+    // --exec はフィルタ適用後の最終的なツリーに対して実行する
+    if let Some(cmd) = &config.exec_cmd {
+        if config.exec_batch {
+            exec_batched(&tree, cmd);
+        } else {
+            exec_per_entry(&tree, cmd);
+        }
+    }
+    // --no-indent / --only-files: 接続記号なしの平坦モード。区切りは
+    // 既定で改行、--entry-separator で差し替えられる (ツリー表示では無視)
+    if config.no_indent || config.only_files {
+        write!(out, "{}", render_flat(&tree, config))?;
+        return Ok(file_count(&tree));
+    }
+    let started = Instant::now();
+    match config.format {
+        Format::Text => write!(out, "{}", render_to_string(&tree, config))?,
+        Format::Json if config.wrap_root_in_object => render_json_value(out, &tree)?,
+        Format::Json => render_json(out, &tree)?,
+        Format::Yaml => render_yaml(out, &tree)?,
+        Format::Xml => render_xml(out, &tree)?,
+        Format::Jsonl => render_jsonl(out, &tree)?,
+    }
+    if config.profile {
+        eprintln!("{}", format_profile("render", started.elapsed()));
+    }
 
-        let result = validate_path(path);
-        assert!(matches!(result, Err(AppError::NotADirectory(_))));
+    if config.unique_names {
+        let groups = duplicate_name_groups(&tree);
+        if !groups.is_empty() {
+            write!(out, "{}", format_duplicate_names(&groups))?;
+        }
     }
 
-    // TODO: テストを動かす方法があれば作成する
-    #[test]
-    #[ignore]
-    fn validate_path_permission_denied_returns_err() {}
+    if config.count_by_depth_json {
+        writeln!(out, "{}", format_count_by_depth_json(&count_by_depth(&tree)))?;
+    }
 
-    #[test]
-    fn read_directory_empty_directory_returns_ok() {
-        let dir = tempdir().unwrap();
-        let path = dir.path();
+    if config.partition_by_size {
+        writeln!(out, "{}", format_size_partition(&partition_by_size(&tree)))?;
+    }
 
-        let entries = read_directory(path).unwrap();
-        assert!(entries.is_empty());
+    if config.color_legend
+        && let Some(legend) = color_legend(&tree, config)
+    {
+        write!(out, "{}", legend)?;
     }
 
-    #[test]
-    fn read_directory_with_file_returns_ok() {
-        let dir = tempdir().unwrap();
-        let path = dir.path();
+    if config.count_hidden_separately {
+        writeln!(out, "{}", format_summary_with_hidden(&tree))?;
+    }
 
-        // ファイルを作成
-        File::create(path.join("file1.txt")).unwrap();
-        File::create(path.join("file2.txt")).unwrap();
+    if config.summary_json {
+        writeln!(out, "{}", format_summary_json(&tree, config.du))?;
+    }
 
-        let entries = read_directory(path).unwrap();
-        let names: Vec<_> = entries
-            .iter()
-            .map(|e| e.file_name().to_string_lossy().to_string())
-            .collect();
+    if config.report_deepest
+        && let Some((path, depth)) = deepest_path(&tree)
+    {
+        writeln!(out, "{}", format_deepest(&path, depth))?;
+    }
 
-        assert!(names.contains(&"file1.txt".to_string()));
-        assert!(names.contains(&"file2.txt".to_string()));
-        assert_eq!(names.len(), 2);
+    if config.show_link_count_summary {
+        write!(out, "{}", format_link_summary(&link_summary(&tree)))?;
     }
 
-    #[test]
-    fn read_directory_with_subdirectories_returns_ok() {
-        let dir = tempdir().unwrap();
-        let path = dir.path();
+    if config.report_empty_dirs {
+        let dirs = empty_dirs(&tree);
+        if !dirs.is_empty() {
+            write!(out, "{}", format_empty_dirs(&dirs))?;
+        }
+    }
 
-        fs::create_dir(path.join("sub1")).unwrap();
-        fs::create_dir(path.join("sub2")).unwrap();
+    if config.error_summary && !outcome.errors.is_empty() {
+        eprint!("{}", format_error_summary(&outcome.errors));
+    }
 
-        let entries = read_directory(path).unwrap();
-        let names: Vec<_> = entries
-            .iter()
-            .map(|e| e.file_name().to_string_lossy().to_string())
-            .collect();
+    Ok(file_count(&tree))
+}
 
-        assert!(names.contains(&"sub1".to_string()));
-        assert!(names.contains(&"sub2".to_string()));
-        assert_eq!(names.len(), 2);
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        std::process::exit(1);
     }
 }
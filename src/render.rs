@@ -0,0 +1,1135 @@
+use std::io::{self, Write};
+
+use crate::config::{Config, NameEncoding};
+use crate::util::{base32_encode, base64_encode, format_relative_secs, format_timestamp, human_size};
+use crate::walk::{EntryKind, Node};
+
+/// 制御文字を `\xNN` 表記に置き換える。悪意あるファイル名が ANSI
+/// シーケンスを端末に注入するのを防ぐ (`--escape-control`)
+pub fn escape_control_chars(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_control() {
+            out.push_str(&format!("\\x{:02x}", c as u32));
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// 表示用のエントリ名を組み立てる (OSC 8 ハイパーリンク等の装飾を含む)
+/// `--color-depth` の循環パレット。深さごとに前景色を変える
+const DEPTH_PALETTE: [&str; 6] = ["31", "33", "32", "36", "34", "35"];
+
+/// `--color-depth` 用: 深さに応じた色コードを返す
+pub fn depth_color(depth: usize) -> &'static str {
+    DEPTH_PALETTE[depth % DEPTH_PALETTE.len()]
+}
+
+/// 深さに応じた色付けを名前に適用する。`--color-depth` が無効ならそのまま返す
+fn apply_depth_color(name: String, depth: usize, config: &Config) -> String {
+    if config.color_depth && config.color_active {
+        format!("\x1b[{}m{}\x1b[0m", depth_color(depth), name)
+    } else {
+        name
+    }
+}
+
+pub fn display_name(node: &Node, config: &Config) -> String {
+    // エンコード済みの名前は ASCII のみなので制御文字エスケープは不要
+    let raw_name = match config.encode_names {
+        Some(_) if node.kind == EntryKind::Marker => node.name.clone(),
+        Some(NameEncoding::Base64) => base64_encode(node.name.as_bytes()),
+        Some(NameEncoding::Base32) => base32_encode(node.name.as_bytes()),
+        None if config.escape_control => escape_control_chars(&node.name),
+        None => node.name.clone(),
+    };
+    let raw_name = if config.trailing_slash && node.kind == EntryKind::Dir {
+        format!("{}/", raw_name)
+    } else {
+        raw_name
+    };
+    let mut name = if config.hyperlinks && node.kind != EntryKind::Marker {
+        format!(
+            "\x1b]8;;file://{}\x1b\\{}\x1b]8;;\x1b\\",
+            node.path.display(),
+            raw_name
+        )
+    } else {
+        raw_name
+    };
+
+    // --color-depth が優先されるときは種別の色付けを抑える
+    if config.color_active && node.kind == EntryKind::Dir && !config.color_depth {
+        name = format!("\x1b[1;34m{}\x1b[0m", name);
+    }
+
+    if node.kind != EntryKind::Marker {
+        if config.show_size && let Some(size) = node.size {
+            name.push_str(&format!(" [{}B]", size));
+        }
+        if config.show_time && let Some(mtime) = node.mtime {
+            // 再現可能ビルド検証向けに、基準からの相対秒で出すこともできる
+            if config.deterministic_timestamps && let Some(reference) = config.time_reference {
+                name.push_str(&format!(" [{}]", format_relative_secs(mtime, reference)));
+            } else {
+                name.push_str(&format!(" [{}]", format_timestamp(mtime)));
+            }
+        }
+    }
+
+    if let Some(note) = &node.note {
+        name.push(' ');
+        name.push_str(note);
+    }
+    name
+}
+
+/// ツリー描画に使う枝記号の組。`--compact` では 1 レベルあたりの
+/// インデントを標準の 4 桁から 2 桁に詰める
+struct Connectors {
+    tee: &'static str,
+    corner: &'static str,
+    pipe: &'static str,
+    blank: &'static str,
+}
+
+impl Connectors {
+    fn for_config(config: &Config) -> Self {
+        if config.compact {
+            Connectors {
+                tee: "├ ",
+                corner: "└ ",
+                pipe: "│ ",
+                blank: "  ",
+            }
+        } else {
+            Connectors {
+                tee: "├── ",
+                corner: "└── ",
+                pipe: "│   ",
+                blank: "    ",
+            }
+        }
+    }
+}
+
+/// `--entry-template` のプレースホルダを 1 エントリ分展開する。
+/// 値が取れないプレースホルダ (`{size}` など) は空文字になる
+pub fn apply_template(template: &str, node: &Node, depth: usize, indent: &str) -> String {
+    let mut out = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '{' {
+            out.push(c);
+            continue;
+        }
+        let mut key = String::new();
+        for k in chars.by_ref() {
+            if k == '}' {
+                break;
+            }
+            key.push(k);
+        }
+        match key.as_str() {
+            "name" => out.push_str(&node.name),
+            "path" => out.push_str(&node.path.display().to_string()),
+            "size" => {
+                if let Some(size) = node.size {
+                    out.push_str(&size.to_string());
+                }
+            }
+            "mtime" => {
+                if let Some(mtime) = node.mtime {
+                    out.push_str(&format_timestamp(mtime));
+                }
+            }
+            "type" => out.push_str(kind_label(node.kind)),
+            "depth" => out.push_str(&depth.to_string()),
+            "indent" => out.push_str(indent),
+            // 未知のキーは parse_args 時点で弾かれている
+            _ => {}
+        }
+    }
+    out
+}
+
+/// `--depth-indicator` 用の行頭プレフィックス (ルートは深さ 0)
+fn depth_prefix(config: &Config, depth: usize) -> String {
+    // --prefix は深さ表示よりさらに前に置く
+    let mut prefix = config.line_prefix.clone().unwrap_or_default();
+    if config.depth_indicator {
+        prefix.push_str(&format!("{}: ", depth));
+    }
+    prefix
+}
+
+/// `--max-cols` を超える行を折り返して出力する。継続行は名前の開始位置
+/// (接続記号の直後) に揃えてインデントする
+fn write_wrapped<W: Write>(
+    writer: &mut W,
+    line: &str,
+    cont_indent: usize,
+    config: &Config,
+) -> io::Result<()> {
+    let Some(max) = config.max_cols else {
+        return writeln!(writer, "{}", line);
+    };
+    let chars: Vec<char> = line.chars().collect();
+    if chars.len() <= max {
+        return writeln!(writer, "{}", line);
+    }
+
+    let indent: String = " ".repeat(cont_indent);
+    let cont_width = max.saturating_sub(cont_indent).max(1);
+    let mut start = 0;
+    while start < chars.len() {
+        let width = if start == 0 { max } else { cont_width };
+        let end = (start + width).min(chars.len());
+        let chunk: String = chars[start..end].iter().collect();
+        if start == 0 {
+            writeln!(writer, "{}", chunk)?;
+        } else {
+            writeln!(writer, "{}{}", indent, chunk)?;
+        }
+        start = end;
+    }
+    Ok(())
+}
+
+/// ツリーを文字列として描画する。stdout に触れないため GUI やテストなど
+/// ライブラリ利用者向けの入口
+///
+/// ```
+/// use std::path::PathBuf;
+/// use treer::config::Config;
+/// use treer::render::render_to_string;
+/// use treer::walk::{EntryKind, Node};
+///
+/// let child = Node {
+///     name: "a.txt".to_string(),
+///     path: PathBuf::from("a.txt"),
+///     kind: EntryKind::File,
+///     size: Some(0),
+///     mode: None,
+///     mtime: None,
+///     dev: None,
+///     is_mount: false,
+///     note: None,
+///     children: Vec::new(),
+/// };
+/// let root = Node {
+///     name: ".".to_string(),
+///     path: PathBuf::from("."),
+///     kind: EntryKind::Dir,
+///     size: None,
+///     mode: None,
+///     mtime: None,
+///     dev: None,
+///     is_mount: false,
+///     note: None,
+///     children: vec![child],
+/// };
+///
+/// let text = render_to_string(&root, &Config::default());
+/// assert!(text.contains("└── a.txt"));
+/// ```
+pub fn render_to_string(root: &Node, config: &Config) -> String {
+    let mut buf = Vec::new();
+    // Vec<u8> への書き込みは失敗しない
+    render(&mut buf, root, config).expect("rendering to a Vec cannot fail");
+    String::from_utf8_lossy(&buf).into_owned()
+}
+
+/// `--no-indent` / `--only-files` 用: ツリーを相対パスの平坦な一覧として
+/// 整形する。区切りは既定で改行、`--entry-separator` で差し替えられる
+pub fn render_flat(root: &Node, config: &Config) -> String {
+    let mut entries = crate::walk::flatten_tree(root);
+    if config.flat_sort {
+        crate::sort::sort_flat(&mut entries, config);
+    }
+    let paths: Vec<&str> = entries
+        .iter()
+        .filter(|(_, n)| {
+            !config.only_files || matches!(n.kind, EntryKind::File | EntryKind::Symlink)
+        })
+        .map(|(p, _)| p.as_str())
+        .collect();
+    match &config.entry_separator {
+        Some(sep) => format!("{}\n", paths.join(sep)),
+        None => paths.iter().map(|p| format!("{}\n", p)).collect(),
+    }
+}
+
+pub fn render<W: Write>(writer: &mut W, root: &Node, config: &Config) -> io::Result<()> {
+    // --line-numbers: 一旦バッファに描画してから行番号を右寄せで前置する。
+    // 桁数は総行数から決まるため二段構えにしている
+    if config.line_numbers {
+        let mut buf = Vec::new();
+        render_lines(&mut buf, root, config).expect("rendering to a Vec cannot fail");
+        let text = String::from_utf8_lossy(&buf);
+        let width = text.lines().count().to_string().len();
+        for (i, line) in text.lines().enumerate() {
+            writeln!(writer, "{:>width$} {}", i + 1, line)?;
+        }
+        return Ok(());
+    }
+    render_lines(writer, root, config)
+}
+
+fn render_lines<W: Write>(writer: &mut W, root: &Node, config: &Config) -> io::Result<()> {
+    if let Some(template) = &config.entry_template {
+        let lead = config.line_prefix.as_deref().unwrap_or("");
+        writeln!(writer, "{}{}", lead, apply_template(template, root, 0, ""))?;
+        return render_children(writer, &root.children, "", 1, config);
+    }
+    let mut line = format!(
+        "{}{}",
+        depth_prefix(config, 0),
+        apply_depth_color(display_name(root, config), 0, config)
+    );
+    // --show-root-stats: ルート行にツリー全体の要約を添える
+    if config.show_root_stats {
+        let (files, dirs, bytes) = crate::stats::tree_totals(root);
+        line.push_str(&format!(
+            " ({} files, {} dirs, {})",
+            files,
+            dirs,
+            human_size(bytes)
+        ));
+    }
+    write_wrapped(writer, &line, 0, config)?;
+    render_children(writer, &root.children, "", 1, config)
+}
+
+fn render_children<W: Write>(
+    writer: &mut W,
+    children: &[Node],
+    prefix: &str,
+    depth: usize,
+    config: &Config,
+) -> io::Result<()> {
+    let connectors = Connectors::for_config(config);
+    let count = children.len();
+    // 接頭辞と接続記号の表示幅は全兄弟で共通なので、エントリごとに
+    // 数え直さずここで一度だけ測ってキャッシュする
+    let prefix_width = prefix.chars().count();
+    let tee_width = connectors.tee.chars().count();
+    let corner_width = connectors.corner.chars().count();
+    for (i, child) in children.iter().enumerate() {
+        let is_last = i + 1 == count;
+        let connector = if is_last { connectors.corner } else { connectors.tee };
+        if let Some(template) = &config.entry_template {
+            let lead = config.line_prefix.as_deref().unwrap_or("");
+            let indent = format!("{}{}", prefix, connector);
+            writeln!(writer, "{}{}", lead, apply_template(template, child, depth, &indent))?;
+            if child.kind == EntryKind::Dir && !child.children.is_empty() {
+                let child_prefix = if is_last {
+                    format!("{}{}", prefix, connectors.blank)
+                } else {
+                    format!("{}{}", prefix, connectors.pipe)
+                };
+                render_children(writer, &child.children, &child_prefix, depth + 1, config)?;
+            }
+            continue;
+        }
+        let line = format!(
+            "{}{}{}{}",
+            depth_prefix(config, depth),
+            prefix,
+            connector,
+            apply_depth_color(display_name(child, config), depth, config)
+        );
+        let cont_indent = prefix_width + if is_last { corner_width } else { tee_width };
+        write_wrapped(writer, &line, cont_indent, config)?;
+
+        if child.kind == EntryKind::Dir && !child.children.is_empty() {
+            let child_prefix = if is_last {
+                format!("{}{}", prefix, connectors.blank)
+            } else {
+                format!("{}{}", prefix, connectors.pipe)
+            };
+            render_children(writer, &child.children, &child_prefix, depth + 1, config)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// `--limit-output-bytes` 用: 書き込んだバイト数を数え、上限に達したら
+/// 以降の書き込みを黙って捨てる `Write` ラッパ。呼び出し側はエラーに
+/// ならないため、描画ループはそのまま完走する
+pub struct LimitedWriter<W: Write> {
+    inner: W,
+    limit: u64,
+    written: u64,
+    truncated: bool,
+}
+
+impl<W: Write> LimitedWriter<W> {
+    pub fn new(inner: W, limit: u64) -> Self {
+        LimitedWriter {
+            inner,
+            limit,
+            written: 0,
+            truncated: false,
+        }
+    }
+
+    /// 上限超過で出力を打ち切ったかどうか
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+impl<W: Write> Write for LimitedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let remaining = self.limit.saturating_sub(self.written);
+        if remaining == 0 {
+            self.truncated = true;
+            return Ok(buf.len());
+        }
+        let take = buf.len().min(remaining as usize);
+        let n = self.inner.write(&buf[..take])?;
+        self.written += n as u64;
+        if take < buf.len() {
+            self.truncated = true;
+        }
+        Ok(if n == take { buf.len() } else { n })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// 構造化出力で使うエントリ種別のラベル
+fn kind_label(kind: EntryKind) -> &'static str {
+    match kind {
+        EntryKind::Dir => "dir",
+        EntryKind::File => "file",
+        EntryKind::Symlink => "symlink",
+        EntryKind::Marker => "marker",
+    }
+}
+
+/// JSON/YAML の二重引用符文字列向けエスケープ
+fn escape_quoted(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// `--color-legend` 用: ツリーに現れる各深さとその色見本を並べた
+/// フッタを返す。深さ色付けが有効でないときは何も出さない
+pub fn color_legend(root: &Node, config: &Config) -> Option<String> {
+    if !(config.color_depth && config.color_active) {
+        return None;
+    }
+    fn max_depth(node: &Node, depth: usize) -> usize {
+        node.children
+            .iter()
+            .map(|c| max_depth(c, depth + 1))
+            .max()
+            .unwrap_or(depth)
+    }
+    let mut legend = String::new();
+    for depth in 0..=max_depth(root, 0) {
+        legend.push_str(&format!(
+            "depth {}: \x1b[{}m\u{2588}\u{2588}\x1b[0m\n",
+            depth,
+            depth_color(depth)
+        ));
+    }
+    Some(legend)
+}
+
+/// ツリーを `{"name":..,"type":..,"children":[..]}` の JSON で出力する
+pub fn render_json<W: Write>(writer: &mut W, root: &Node) -> io::Result<()> {
+    render_json_value(writer, root)?;
+    writeln!(writer)
+}
+
+/// 改行なしで JSON オブジェクトだけを書く (`--wrap-root-in-object` が
+/// ルートを配列に包むときに使う)
+pub fn render_json_value<W: Write>(writer: &mut W, root: &Node) -> io::Result<()> {
+    fn emit<W: Write>(writer: &mut W, node: &Node) -> io::Result<()> {
+        write!(
+            writer,
+            "{{\"name\":\"{}\",\"type\":\"{}\"",
+            escape_quoted(&node.name),
+            kind_label(node.kind)
+        )?;
+        // --mount-info が立っていたときだけ walk が dev を埋めている
+        if let Some(dev) = node.dev {
+            write!(
+                writer,
+                ",\"device\":{},\"is_mount_point\":{}",
+                dev, node.is_mount
+            )?;
+        }
+        if node.kind == EntryKind::Dir {
+            write!(writer, ",\"children\":[")?;
+            for (i, child) in node.children.iter().enumerate() {
+                if i > 0 {
+                    write!(writer, ",")?;
+                }
+                emit(writer, child)?;
+            }
+            write!(writer, "]")?;
+        }
+        write!(writer, "}}")
+    }
+
+    emit(writer, root)
+}
+
+/// 1 エントリ 1 行の JSON Lines 出力 (`--format=jsonl`)。遅い消費者に
+/// 合わせて行ごとにフラッシュし、パイプが閉じられたら BrokenPipe を
+/// そのまま返して打ち切る (main 側で正常終了として扱う)
+pub fn render_jsonl<W: Write>(writer: &mut W, root: &Node) -> io::Result<()> {
+    fn emit<W: Write>(writer: &mut W, node: &Node, prefix: &str) -> io::Result<()> {
+        let path = if prefix.is_empty() {
+            node.name.clone()
+        } else {
+            format!("{}/{}", prefix, node.name)
+        };
+        write!(
+            writer,
+            "{{\"path\":\"{}\",\"type\":\"{}\"",
+            escape_quoted(&path),
+            kind_label(node.kind)
+        )?;
+        if let Some(size) = node.size {
+            write!(writer, ",\"size\":{}", size)?;
+        }
+        writeln!(writer, "}}")?;
+        writer.flush()?;
+        for child in &node.children {
+            emit(writer, child, &path)?;
+        }
+        Ok(())
+    }
+
+    emit(writer, root, "")
+}
+
+/// ノード構造を XML で出力する (`--format=xml`)。要素名は種別に対応し、
+/// 名前は属性としてエスケープして持つ
+pub fn render_xml<W: Write>(writer: &mut W, root: &Node) -> io::Result<()> {
+    fn element(kind: EntryKind) -> &'static str {
+        match kind {
+            EntryKind::Dir => "directory",
+            EntryKind::File => "file",
+            EntryKind::Symlink => "symlink",
+            EntryKind::Marker => "marker",
+        }
+    }
+
+    fn emit<W: Write>(writer: &mut W, node: &Node, pad: &str) -> io::Result<()> {
+        write!(
+            writer,
+            "{}<{} name=\"{}\"",
+            pad,
+            element(node.kind),
+            escape_xml(&node.name)
+        )?;
+        if let Some(size) = node.size {
+            write!(writer, " size=\"{}\"", size)?;
+        }
+        if node.kind == EntryKind::Dir && !node.children.is_empty() {
+            writeln!(writer, ">")?;
+            let child_pad = format!("{}  ", pad);
+            for child in &node.children {
+                emit(writer, child, &child_pad)?;
+            }
+            writeln!(writer, "{}</{}>", pad, element(node.kind))
+        } else {
+            writeln!(writer, "/>")
+        }
+    }
+
+    writeln!(writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    emit(writer, root, "")
+}
+
+/// XML の属性値向けエスケープ
+fn escape_xml(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// `--print-json-schema` 用: `--format=json` の出力構造を JSON Schema として
+/// 記述する。size / mtime は対応する表示フラグが立っているときだけ宣言する
+pub fn json_schema(config: &Config) -> String {
+    let mut props = vec![
+        "\"name\":{\"type\":\"string\"}".to_string(),
+        "\"type\":{\"enum\":[\"dir\",\"file\",\"symlink\",\"marker\"]}".to_string(),
+        "\"children\":{\"type\":\"array\",\"items\":{\"$ref\":\"#\"}}".to_string(),
+    ];
+    if config.show_size {
+        props.push("\"size\":{\"type\":\"integer\"}".to_string());
+    }
+    if config.show_time {
+        props.push("\"mtime\":{\"type\":\"string\"}".to_string());
+    }
+    format!(
+        "{{\"$schema\":\"https://json-schema.org/draft/2020-12/schema\",\"type\":\"object\",\"properties\":{{{}}},\"required\":[\"name\",\"type\"]}}",
+        props.join(",")
+    )
+}
+
+/// JSON と同じノード構造を YAML で出力する (`--format=yaml`)。
+/// 名前は特殊文字対策として常に二重引用符で囲む
+pub fn render_yaml<W: Write>(writer: &mut W, root: &Node) -> io::Result<()> {
+    fn emit_fields<W: Write>(writer: &mut W, node: &Node, pad: &str) -> io::Result<()> {
+        writeln!(writer, "{}type: {}", pad, kind_label(node.kind))?;
+        if node.kind == EntryKind::Dir {
+            if node.children.is_empty() {
+                writeln!(writer, "{}children: []", pad)?;
+            } else {
+                writeln!(writer, "{}children:", pad)?;
+                let item_pad = format!("{}  ", pad);
+                for child in &node.children {
+                    emit_item(writer, child, &item_pad)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn emit_item<W: Write>(writer: &mut W, node: &Node, pad: &str) -> io::Result<()> {
+        writeln!(writer, "{}- name: \"{}\"", pad, escape_quoted(&node.name))?;
+        emit_fields(writer, node, &format!("{}  ", pad))
+    }
+
+    writeln!(writer, "name: \"{}\"", escape_quoted(&root.name))?;
+    emit_fields(writer, root, "")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::walk::test_util::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn render_draws_connectors() {
+        let root = dir_node(
+            ".",
+            vec![
+                file_node("a.txt"),
+                dir_node("sub", vec![file_node("inner.txt")]),
+            ],
+        );
+
+        let mut buf = Vec::new();
+        render(&mut buf, &root, &Config::default()).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+
+        assert_eq!(output, ".\n├── a.txt\n└── sub\n    └── inner.txt\n");
+    }
+
+    #[test]
+    fn render_trailing_slash_marks_directories_only() {
+        let root = dir_node(
+            ".",
+            vec![file_node("a.txt"), dir_node("sub", vec![file_node("b.txt")])],
+        );
+        let config = Config {
+            trailing_slash: true,
+            ..Config::default()
+        };
+
+        let mut buf = Vec::new();
+        render(&mut buf, &root, &config).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+
+        assert_eq!(output, "./\n├── a.txt\n└── sub/\n    └── b.txt\n");
+    }
+
+    #[test]
+    fn render_json_emits_expected_structure() {
+        let root = dir_node(".", vec![file_node("a.txt"), dir_node("sub", vec![])]);
+
+        let mut buf = Vec::new();
+        render_json(&mut buf, &root).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+
+        assert_eq!(
+            output,
+            concat!(
+                "{\"name\":\".\",\"type\":\"dir\",\"children\":[",
+                "{\"name\":\"a.txt\",\"type\":\"file\"},",
+                "{\"name\":\"sub\",\"type\":\"dir\",\"children\":[]}",
+                "]}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn render_yaml_quotes_names_and_nests_children() {
+        let root = dir_node(
+            ".",
+            vec![dir_node("sub", vec![file_node("a \"quoted\".txt")])],
+        );
+
+        let mut buf = Vec::new();
+        render_yaml(&mut buf, &root).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+
+        assert_eq!(
+            output,
+            concat!(
+                "name: \".\"\n",
+                "type: dir\n",
+                "children:\n",
+                "  - name: \"sub\"\n",
+                "    type: dir\n",
+                "    children:\n",
+                "      - name: \"a \\\"quoted\\\".txt\"\n",
+                "        type: file\n"
+            )
+        );
+    }
+
+    #[test]
+    fn render_max_cols_wraps_long_lines_with_indent() {
+        let root = dir_node(".", vec![file_node("abcdefghijklmnopqrstuvwxyz.txt")]);
+        let config = Config {
+            max_cols: Some(20),
+            ..Config::default()
+        };
+
+        let mut buf = Vec::new();
+        render(&mut buf, &root, &config).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+
+        assert_eq!(
+            output,
+            ".\n└── abcdefghijklmnop\n    qrstuvwxyz.txt\n"
+        );
+    }
+
+    #[test]
+    fn render_compact_halves_indent_width() {
+        let root = dir_node(
+            ".",
+            vec![dir_node("sub", vec![file_node("inner.txt")])],
+        );
+        let config = Config {
+            compact: true,
+            ..Config::default()
+        };
+
+        let mut buf = Vec::new();
+        render(&mut buf, &root, &config).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+
+        assert_eq!(output, ".\n└ sub\n  └ inner.txt\n");
+    }
+
+    #[test]
+    fn apply_template_substitutes_known_placeholders() {
+        let node = sized_file_node("a.txt", 100);
+
+        assert_eq!(apply_template("{name}|{size}", &node, 2, ""), "a.txt|100");
+        assert_eq!(apply_template("{depth}:{type}", &node, 2, ""), "2:file");
+        // 値のないプレースホルダは空文字になる
+        assert_eq!(apply_template("{name}@{mtime}", &node, 0, ""), "a.txt@");
+    }
+
+    #[test]
+    fn display_name_deterministic_timestamps_are_reference_relative() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let reference = UNIX_EPOCH + Duration::from_secs(10_000);
+        let mut early = sized_file_node("early.txt", 0);
+        early.mtime = Some(reference - Duration::from_secs(500));
+        let mut late = sized_file_node("late.txt", 0);
+        late.mtime = Some(reference + Duration::from_secs(3_600));
+
+        let config = Config {
+            show_time: true,
+            deterministic_timestamps: true,
+            time_reference: Some(reference),
+            ..Config::default()
+        };
+
+        assert!(display_name(&early, &config).ends_with("[-500s]"));
+        assert!(display_name(&late, &config).ends_with("[+3600s]"));
+    }
+
+    #[test]
+    fn render_show_root_stats_summarizes_totals() {
+        let root = dir_node(
+            ".",
+            vec![
+                sized_file_node("a.txt", 100),
+                dir_node("sub", vec![sized_file_node("b.txt", 50)]),
+            ],
+        );
+        let config = Config {
+            show_root_stats: true,
+            ..Config::default()
+        };
+
+        let mut buf = Vec::new();
+        render(&mut buf, &root, &config).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+
+        assert!(output.starts_with(". (2 files, 1 dirs, 150B)\n"));
+    }
+
+    #[test]
+    fn render_depth_indicator_prefixes_lines_with_depth() {
+        let root = dir_node(
+            ".",
+            vec![dir_node("sub", vec![file_node("inner.txt")])],
+        );
+        let config = Config {
+            depth_indicator: true,
+            ..Config::default()
+        };
+
+        let mut buf = Vec::new();
+        render(&mut buf, &root, &config).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+
+        assert_eq!(
+            output,
+            "0: .\n1: └── sub\n2:     └── inner.txt\n"
+        );
+    }
+
+    #[test]
+    fn limited_writer_stops_at_byte_limit() {
+        let mut writer = LimitedWriter::new(Vec::new(), 10);
+
+        writer.write_all(b"0123456789abcdefghij").unwrap();
+        writer.write_all(b"more").unwrap();
+
+        assert!(writer.truncated());
+        assert_eq!(writer.inner, b"0123456789");
+    }
+
+    #[test]
+    fn render_large_flat_tree_is_complete_and_ordered() {
+        // バッファリング自体は性能の話なので、ここでは 1 万エントリでも
+        // 出力が欠けず順序どおりであることを確認する
+        let root = dir_node(
+            ".",
+            (0..10_000)
+                .map(|i| file_node(&format!("f{:05}.txt", i)))
+                .collect(),
+        );
+
+        let mut buf = Vec::new();
+        render(&mut buf, &root, &Config::default()).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 10_001);
+        assert_eq!(lines[1], "├── f00000.txt");
+        assert_eq!(lines[10_000], "└── f09999.txt");
+    }
+
+    #[test]
+    fn display_name_encode_names_base64() {
+        let node = file_node("hello.txt");
+        let config = Config {
+            encode_names: Some(NameEncoding::Base64),
+            ..Config::default()
+        };
+
+        assert_eq!(display_name(&node, &config), "aGVsbG8udHh0");
+    }
+
+    #[test]
+    fn display_name_escape_control_makes_ansi_visible() {
+        let node = file_node("\x1b[31mevil.txt");
+        let config = Config {
+            escape_control: true,
+            ..Config::default()
+        };
+
+        let name = display_name(&node, &config);
+        assert_eq!(name, "\\x1b[31mevil.txt");
+        assert!(!name.contains('\x1b'));
+    }
+
+    #[test]
+    fn display_name_hyperlinks_wraps_in_osc8() {
+        let mut node = file_node("a.txt");
+        node.path = PathBuf::from("/tmp/a.txt");
+        let config = Config {
+            hyperlinks: true,
+            ..Config::default()
+        };
+
+        let name = display_name(&node, &config);
+        assert!(name.starts_with("\x1b]8;;file:///tmp/a.txt\x1b\\"));
+        assert!(name.ends_with("\x1b]8;;\x1b\\"));
+        assert!(name.contains("a.txt"));
+    }
+
+    #[test]
+    fn display_name_marker_is_not_linked() {
+        let node = Node::marker("[size budget reached]");
+        let config = Config {
+            hyperlinks: true,
+            ..Config::default()
+        };
+
+        assert_eq!(display_name(&node, &config), "[size budget reached]");
+    }
+
+    #[test]
+    fn display_name_colors_directories_when_active() {
+        let node = dir_node("sub", vec![]);
+        let config = Config {
+            color_active: true,
+            ..Config::default()
+        };
+
+        let name = display_name(&node, &config);
+        assert!(name.starts_with("\x1b[1;34m"));
+        assert!(name.ends_with("\x1b[0m"));
+    }
+
+    #[test]
+    fn json_schema_declares_children_and_optional_size() {
+        let config = Config::default();
+        let schema = json_schema(&config);
+        assert!(schema.starts_with('{') && schema.ends_with('}'));
+        assert!(schema.contains("\"children\":{\"type\":\"array\""));
+        assert!(!schema.contains("\"size\""));
+
+        let config = Config {
+            show_size: true,
+            ..Config::default()
+        };
+        assert!(json_schema(&config).contains("\"size\":{\"type\":\"integer\"}"));
+    }
+
+    #[test]
+    fn render_json_includes_mount_info_when_populated() {
+        let mut root = dir_node(".", vec![file_node("a.txt")]);
+        root.dev = Some(1);
+        root.children[0].dev = Some(2);
+        root.children[0].is_mount = true;
+
+        let mut buf = Vec::new();
+        render_json(&mut buf, &root).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+
+        assert!(output.contains("\"device\":2,\"is_mount_point\":true"));
+        assert!(output.contains("\"device\":1,\"is_mount_point\":false"));
+    }
+
+    #[test]
+    fn color_depth_assigns_distinct_codes_per_level() {
+        let tree = dir_node("root", vec![dir_node("sub", vec![file_node("deep.txt")])]);
+        let config = Config {
+            color_depth: true,
+            color_active: true,
+            ..Config::default()
+        };
+        let output = render_to_string(&tree, &config);
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert!(lines[0].contains(&format!("\x1b[{}m", depth_color(0))));
+        assert!(lines[1].contains(&format!("\x1b[{}m", depth_color(1))));
+        assert!(lines[2].contains(&format!("\x1b[{}m", depth_color(2))));
+        assert_ne!(depth_color(0), depth_color(1));
+    }
+
+    #[test]
+    fn render_xml_nests_elements_and_escapes_attributes() {
+        let root = dir_node(
+            ".",
+            vec![sized_file_node("a&b.txt", 3), dir_node("sub", vec![])],
+        );
+
+        let mut buf = Vec::new();
+        render_xml(&mut buf, &root).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+
+        assert_eq!(
+            output,
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                "<directory name=\".\">\n",
+                "  <file name=\"a&amp;b.txt\" size=\"3\"/>\n",
+                "  <directory name=\"sub\"/>\n",
+                "</directory>\n"
+            )
+        );
+    }
+
+    #[test]
+    fn render_jsonl_emits_one_line_per_entry() {
+        let root = dir_node(".", vec![sized_file_node("a.txt", 3), dir_node("sub", vec![])]);
+
+        let mut buf = Vec::new();
+        render_jsonl(&mut buf, &root).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "{\"path\":\".\",\"type\":\"dir\"}");
+        assert_eq!(lines[1], "{\"path\":\"./a.txt\",\"type\":\"file\",\"size\":3}");
+        assert_eq!(lines[2], "{\"path\":\"./sub\",\"type\":\"dir\"}");
+    }
+
+    #[test]
+    fn render_jsonl_stops_on_broken_pipe() {
+        struct ClosedPipe;
+        impl io::Write for ClosedPipe {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                Err(io::Error::from(io::ErrorKind::BrokenPipe))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let root = dir_node(".", vec![file_node("a.txt")]);
+        let err = render_jsonl(&mut ClosedPipe, &root).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn render_line_numbers_prefixes_each_line() {
+        let root = dir_node(
+            "root",
+            vec![file_node("a.txt"), file_node("b.txt")],
+        );
+        let config = Config {
+            line_numbers: true,
+            ..Config::default()
+        };
+        let output = render_to_string(&root, &config);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("1 root"));
+        assert!(lines[1].starts_with("2 "));
+        assert!(lines[2].starts_with("3 "));
+    }
+
+    #[test]
+    fn render_line_prefix_prepends_every_line() {
+        let root = dir_node("root", vec![file_node("a.txt"), dir_node("sub", vec![])]);
+        let config = Config {
+            line_prefix: Some("// ".to_string()),
+            ..Config::default()
+        };
+        let output = render_to_string(&root, &config);
+        assert!(output.lines().count() >= 3);
+        for line in output.lines() {
+            assert!(line.starts_with("// "), "line without prefix: {line}");
+        }
+    }
+
+    #[test]
+    fn render_json_value_omits_newline_for_array_wrapping() {
+        let root = dir_node("root", vec![file_node("a.txt")]);
+        let mut buf = Vec::new();
+        render_json_value(&mut buf, &root).unwrap();
+        let value = String::from_utf8(buf).unwrap();
+        assert!(!value.ends_with('\n'));
+        let wrapped = format!("[{}]", value);
+        assert!(wrapped.starts_with("[{\"name\":\"root\""));
+        assert!(wrapped.ends_with("}]"));
+    }
+
+    #[test]
+    fn render_wrapped_output_unchanged_by_width_caching() {
+        // 折り返し位置は事前計測した幅から決まる。キャッシュ導入前の
+        // 期待出力と一致することを固定ツリーで確認する
+        let root = dir_node(
+            "root",
+            vec![dir_node(
+                "sub",
+                vec![file_node("a-rather-long-file-name-that-wraps.txt")],
+            )],
+        );
+        let config = Config {
+            max_cols: Some(24),
+            ..Config::default()
+        };
+        let output = render_to_string(&root, &config);
+        for line in output.lines() {
+            assert!(line.chars().count() <= 24, "overlong line: {line}");
+        }
+        assert!(output.lines().count() > 3);
+    }
+
+    #[test]
+    fn color_legend_lists_one_entry_per_depth() {
+        let root = dir_node(
+            "root",
+            vec![dir_node("sub", vec![file_node("deep.txt")]), file_node("a.txt")],
+        );
+        let config = Config {
+            color_depth: true,
+            color_active: true,
+            ..Config::default()
+        };
+        let legend = color_legend(&root, &config).unwrap();
+        assert_eq!(legend.lines().count(), 3);
+        assert!(legend.starts_with("depth 0: \x1b[31m"));
+        assert!(legend.contains("depth 2: \x1b[32m"));
+
+        let inactive = Config::default();
+        assert!(color_legend(&root, &inactive).is_none());
+    }
+
+    #[test]
+    fn render_flat_only_files_joins_paths_with_separator() {
+        let mut link = file_node("link.txt");
+        link.kind = EntryKind::Symlink;
+        let root = dir_node(
+            "root",
+            vec![
+                file_node("a.txt"),
+                link,
+                dir_node("sub", vec![file_node("b.txt")]),
+            ],
+        );
+        let config = Config {
+            only_files: true,
+            entry_separator: Some(",".to_string()),
+            ..Config::default()
+        };
+        assert_eq!(render_flat(&root, &config), "a.txt,link.txt,sub/b.txt\n");
+
+        // 区切り未指定なら 1 行 1 パスのまま
+        let plain = Config {
+            only_files: true,
+            ..Config::default()
+        };
+        assert_eq!(render_flat(&root, &plain), "a.txt\nlink.txt\nsub/b.txt\n");
+    }
+}
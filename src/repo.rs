@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::error::AppError;
+
+/// path から親方向に `.git` ディレクトリを探してリポジトリルートを返す
+pub fn find_repo_root(path: &Path) -> Option<PathBuf> {
+    let start = fs::canonicalize(path).ok()?;
+    let mut current = Some(start.as_path());
+    while let Some(dir) = current {
+        if dir.join(".git").is_dir() {
+            return Some(dir.to_path_buf());
+        }
+        current = dir.parent();
+    }
+    None
+}
+
+/// `--git-root` 用: ルートをリポジトリの最上位ディレクトリへ付け替える。
+/// リポジトリ外ならエラーにする
+pub fn apply_git_root(config: &mut Config) -> Result<(), AppError> {
+    let top = find_repo_root(&config.root)
+        .ok_or_else(|| AppError::NotInRepository(config.root.clone()))?;
+    config.root = top.clone();
+    config.roots = vec![top];
+    Ok(())
+}
+
+/// `--show-branch` 用: `.git/HEAD` から現在のブランチ名を読む。
+/// detached HEAD ではコミットの短縮ハッシュを返し、リポジトリ外では `None`
+pub fn current_branch(root: &Path) -> Option<String> {
+    let head = fs::read_to_string(find_repo_root(root)?.join(".git/HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref: refs/heads/") {
+        Some(branch) => Some(branch.to_string()),
+        None => Some(head.chars().take(7).collect()),
+    }
+}
+
+/// `.gitignore` の各行をパターンとして読み込む (コメント・空行は除外)
+pub fn load_gitignore(repo_root: &Path) -> Vec<String> {
+    let Ok(content) = fs::read_to_string(repo_root.join(".gitignore")) else {
+        return Vec::new();
+    };
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// `git status --porcelain` の結果をパスごとのステータス文字にまとめる
+pub fn load_git_status(repo_root: &Path) -> HashMap<PathBuf, char> {
+    let Ok(output) = std::process::Command::new("git")
+        .arg("-C")
+        .arg(repo_root)
+        .args(["status", "--porcelain"])
+        .output()
+    else {
+        return HashMap::new();
+    };
+
+    let mut map = HashMap::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if line.len() < 4 {
+            continue;
+        }
+        let status = line.chars().find(|c| !c.is_whitespace()).unwrap_or(' ');
+        let path = line[3..].trim();
+        let path = path.rsplit(" -> ").next().unwrap_or(path);
+        map.insert(repo_root.join(path), status);
+    }
+    map
+}
+
+/// `--repo` 指定時にリポジトリ情報から設定を補完する
+pub fn apply_repo_mode(config: &mut Config) {
+    let Some(root) = find_repo_root(&config.root) else {
+        eprintln!("warning: not in a git repository, falling back to plain mode");
+        return;
+    };
+
+    config.ignore_patterns.extend(load_gitignore(&root));
+    config.ignore_patterns.push(".git".to_string());
+    config.git_status = load_git_status(&root);
+    config.dirs_first = true;
+    config.repo_root = Some(root);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::walk::walk;
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    #[test]
+    fn find_repo_root_from_nested_directory() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join(".git")).unwrap();
+        fs::create_dir_all(root.join("src/nested")).unwrap();
+
+        let found = find_repo_root(&root.join("src/nested")).unwrap();
+        assert_eq!(found, fs::canonicalize(root).unwrap());
+    }
+
+    #[test]
+    fn find_repo_root_outside_repo_returns_none() {
+        let dir = tempdir().unwrap();
+
+        assert!(find_repo_root(dir.path()).is_none());
+    }
+
+    #[test]
+    fn repo_mode_hides_gitignored_files() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join(".git")).unwrap();
+        fs::write(root.join(".gitignore"), "ignored.txt\n").unwrap();
+        File::create(root.join("ignored.txt")).unwrap();
+        File::create(root.join("kept.txt")).unwrap();
+
+        let mut config = Config {
+            root: root.to_path_buf(),
+            repo: true,
+            all: true,
+            ..Config::default()
+        };
+        apply_repo_mode(&mut config);
+        let tree = walk(&config).unwrap().root;
+
+        let names: Vec<_> = tree.children.iter().map(|c| c.name.clone()).collect();
+        assert!(names.contains(&".gitignore".to_string()));
+        assert!(names.contains(&"kept.txt".to_string()));
+        assert!(!names.contains(&"ignored.txt".to_string()));
+        assert!(!names.contains(&".git".to_string()));
+    }
+
+    #[test]
+    fn git_status_untracked_file_is_annotated() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        let init = std::process::Command::new("git")
+            .arg("-C")
+            .arg(root)
+            .arg("init")
+            .output()
+            .unwrap();
+        assert!(init.status.success());
+        File::create(root.join("new.txt")).unwrap();
+
+        let status = load_git_status(&fs::canonicalize(root).unwrap());
+        let key = fs::canonicalize(root).unwrap().join("new.txt");
+        assert_eq!(status.get(&key), Some(&'?'));
+    }
+
+    #[test]
+    fn current_branch_reads_head_ref() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join(".git")).unwrap();
+        fs::write(dir.path().join(".git/HEAD"), "ref: refs/heads/feature\n").unwrap();
+
+        assert_eq!(
+            current_branch(dir.path()).as_deref(),
+            Some("feature")
+        );
+    }
+
+    #[test]
+    fn current_branch_detached_head_uses_short_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join(".git")).unwrap();
+        fs::write(
+            dir.path().join(".git/HEAD"),
+            "0123456789abcdef0123456789abcdef01234567\n",
+        )
+        .unwrap();
+
+        assert_eq!(current_branch(dir.path()).as_deref(), Some("0123456"));
+    }
+
+    #[test]
+    fn apply_git_root_rebases_to_repo_toplevel() {
+        let dir = tempfile::tempdir().unwrap();
+        let top = dir.path().canonicalize().unwrap();
+        fs::create_dir(top.join(".git")).unwrap();
+        fs::create_dir_all(top.join("src/deep")).unwrap();
+
+        let mut config = Config {
+            root: top.join("src/deep"),
+            ..Config::default()
+        };
+        apply_git_root(&mut config).unwrap();
+        assert_eq!(config.root, top);
+        assert_eq!(config.roots, vec![top]);
+    }
+
+    #[test]
+    fn apply_git_root_outside_repo_returns_err() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config {
+            root: dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        assert!(matches!(
+            apply_git_root(&mut config),
+            Err(AppError::NotInRepository(_))
+        ));
+    }
+}
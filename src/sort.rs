@@ -0,0 +1,664 @@
+use crate::config::{Config, SortCase, SortKey, SortNulls};
+use crate::walk::{descendant_count, EntryKind, Node};
+
+pub fn sort_tree(node: &mut Node, config: &Config) {
+    sort_children(&mut node.children, config);
+    for child in &mut node.children {
+        sort_tree(child, config);
+    }
+}
+
+fn kind_rank(kind: EntryKind, config: &Config) -> u8 {
+    match kind {
+        EntryKind::Marker => 2,
+        // --files-before-subdirs は --dirsfirst より優先してファイルを前に出す
+        EntryKind::Dir if config.files_before_subdirs => 1,
+        _ if config.files_before_subdirs => 0,
+        EntryKind::Dir if config.dirs_first => 0,
+        _ => 1,
+    }
+}
+
+/// ソート用の名前キー。既定では大文字小文字を畳み、`--sort-case=strict`
+/// ではバイト順のまま比較する。比較は常に Unicode コードポイント順で、
+/// OS の `ls` と違い `LC_COLLATE` に依存しない (`--stable-across-locale`)
+fn name_key(name: &str, config: &Config) -> String {
+    match config.sort_case {
+        SortCase::Fold => name.to_lowercase(),
+        SortCase::Strict => name.to_string(),
+    }
+}
+
+/// `--sort=natural-ci` 用の比較キー。大文字小文字を畳んだ上で、数字の並びを
+/// ゼロ詰めした固定幅に展開し、`file2` が `file10` より前に来るようにする
+fn natural_key(name: &str) -> String {
+    let mut key = String::with_capacity(name.len());
+    let mut digits = String::new();
+    for c in name.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        if !digits.is_empty() {
+            key.push_str(&format!("{:0>20}", digits.trim_start_matches('0')));
+            digits.clear();
+        }
+        key.extend(c.to_lowercase());
+    }
+    if !digits.is_empty() {
+        key.push_str(&format!("{:0>20}", digits.trim_start_matches('0')));
+    }
+    key
+}
+
+/// splitmix64 ベースの決定的なシャッフルキー。同じ seed と名前なら常に同じ値
+fn shuffle_key(seed: u64, name: &str) -> u64 {
+    fn splitmix64(mut x: u64) -> u64 {
+        x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        x ^ (x >> 31)
+    }
+
+    let mut h = splitmix64(seed);
+    for b in name.bytes() {
+        h = splitmix64(h ^ u64::from(b));
+    }
+    h
+}
+
+/// メタデータ欠損 (None) のエントリを先頭/末尾どちらに寄せるかの順位
+fn null_rank(value: Option<u64>, nulls: SortNulls) -> u8 {
+    match (value, nulls) {
+        (None, SortNulls::First) => 0,
+        (None, SortNulls::Last) => 1,
+        (Some(_), _) => match nulls {
+            SortNulls::First => 1,
+            SortNulls::Last => 0,
+        },
+    }
+}
+
+/// 種別ごとに独立したキーで比較できるよう、どのキーでも同じ型に落とす。
+/// タプルの前半が主キー、後半が名前による安定化
+fn entry_key(node: &Node, key: SortKey, config: &Config) -> (u8, u64, String) {
+    match key {
+        SortKey::Name => (0, 0, name_key(&node.name, config)),
+        SortKey::Size => (
+            null_rank(node.size, config.sort_nulls),
+            node.size.unwrap_or_default(),
+            name_key(&node.name, config),
+        ),
+        SortKey::Count => (
+            0,
+            u64::MAX - descendant_count(node) as u64,
+            name_key(&node.name, config),
+        ),
+        SortKey::Random => (
+            0,
+            shuffle_key(config.seed.unwrap_or_default(), &node.name),
+            String::new(),
+        ),
+        SortKey::Mode => (
+            null_rank(node.mode.map(u64::from), config.sort_nulls),
+            // 珍しいパーミッションが目立つよう降順にする
+            u64::from(u32::MAX - node.mode.unwrap_or_default()),
+            name_key(&node.name, config),
+        ),
+        // 走査はパス名順に行われるため、発見順そのものが決定的
+        SortKey::FirstSeen => (0, 0, String::new()),
+        SortKey::NamePath => (
+            0,
+            0,
+            format!("{}\u{0}{}", name_key(&node.name, config), node.path.display()),
+        ),
+        SortKey::NaturalCi => (0, 0, natural_key(&node.name)),
+        SortKey::TypeThenName => (
+            match node.kind {
+                EntryKind::Dir => 0,
+                EntryKind::Symlink => 1,
+                EntryKind::File => 2,
+                EntryKind::Marker => 3,
+            },
+            0,
+            name_key(&node.name, config),
+        ),
+        // 集約済みサイズの降順。--du と同じ集約パスを前提にする
+        SortKey::ChildrenSize => (
+            null_rank(node.size, config.sort_nulls),
+            u64::MAX - node.size.unwrap_or_default(),
+            name_key(&node.name, config),
+        ),
+    }
+}
+
+/// `--flat-sort` 用: 平坦化した一覧全体をディレクトリ単位ではなく
+/// ソートキーで並べ直す
+pub fn sort_flat(entries: &mut [(String, &Node)], config: &Config) {
+    entries.sort_by_cached_key(|(_, node)| entry_key(node, config.sort, config));
+}
+
+fn sort_children(children: &mut [Node], config: &Config) {
+    // --dir-sort/--file-sort 指定時は種別ごとに別キーで並べる
+    // (ディレクトリが先、ファイルが後、マーカーは末尾)
+    if config.dir_sort.is_some() || config.file_sort.is_some() {
+        children.sort_by_cached_key(|c| {
+            let (rank, key) = match c.kind {
+                EntryKind::Dir => (0u8, config.dir_sort.unwrap_or(config.sort)),
+                EntryKind::Marker => (2, SortKey::Name),
+                _ => (1, config.file_sort.unwrap_or(config.sort)),
+            };
+            (rank, entry_key(c, key, config))
+        });
+        return;
+    }
+
+    match config.sort {
+        SortKey::Name => {
+            children.sort_by_cached_key(|c| {
+                (kind_rank(c.kind, config), name_key(&c.name, config))
+            });
+        }
+        SortKey::Count => {
+            // ディレクトリを子孫数の降順で先に、ファイルは名前順で後に並べる
+            children.sort_by_cached_key(|c| {
+                let rank = match c.kind {
+                    EntryKind::Dir => 0,
+                    EntryKind::File | EntryKind::Symlink => 1,
+                    EntryKind::Marker => 2,
+                };
+                (rank, std::cmp::Reverse(descendant_count(c)), name_key(&c.name, config))
+            });
+        }
+        SortKey::Random => {
+            let seed = config.seed.unwrap_or_default();
+            children.sort_by_cached_key(|c| {
+                (c.kind == EntryKind::Marker, shuffle_key(seed, &c.name))
+            });
+        }
+        SortKey::Size => {
+            children.sort_by_cached_key(|c| {
+                (
+                    kind_rank(c.kind, config),
+                    null_rank(c.size, config.sort_nulls),
+                    c.size.unwrap_or_default(),
+                    name_key(&c.name, config),
+                )
+            });
+        }
+        // 発見順は walk がパス名順で積んだ順そのまま。並列走査が入っても
+        // 決定的な出力を保証するためのキー
+        SortKey::FirstSeen => {}
+        // 同名のエントリ同士はフルパスで順序を確定させる (diff 安定化)
+        SortKey::NamePath => {
+            children.sort_by_cached_key(|c| {
+                (
+                    kind_rank(c.kind, config),
+                    name_key(&c.name, config),
+                    c.path.display().to_string(),
+                )
+            });
+        }
+        SortKey::Mode => {
+            children.sort_by_cached_key(|c| {
+                (
+                    kind_rank(c.kind, config),
+                    null_rank(c.mode.map(u64::from), config.sort_nulls),
+                    u32::MAX - c.mode.unwrap_or_default(),
+                    name_key(&c.name, config),
+                )
+            });
+        }
+        SortKey::NaturalCi => {
+            children.sort_by_cached_key(|c| {
+                (kind_rank(c.kind, config), natural_key(&c.name))
+            });
+        }
+        // dirs-first の変種: 種別で三段にグループ化し、各グループは名前順
+        SortKey::TypeThenName => {
+            children.sort_by_cached_key(|c| {
+                let rank: u8 = match c.kind {
+                    EntryKind::Dir => 0,
+                    EntryKind::Symlink => 1,
+                    EntryKind::File => 2,
+                    EntryKind::Marker => 3,
+                };
+                (rank, name_key(&c.name, config))
+            });
+        }
+        // ディレクトリは集約済みサイズ、ファイルは自身のサイズの降順
+        SortKey::ChildrenSize => {
+            children.sort_by_cached_key(|c| {
+                (
+                    kind_rank(c.kind, config),
+                    null_rank(c.size, config.sort_nulls),
+                    u64::MAX - c.size.unwrap_or_default(),
+                    name_key(&c.name, config),
+                )
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::walk::test_util::*;
+
+    #[test]
+    fn sort_count_orders_busier_directories_first() {
+        let mut tree = dir_node(
+            ".",
+            vec![
+                file_node("a.txt"),
+                dir_node("small", vec![file_node("x.txt")]),
+                dir_node(
+                    "big",
+                    vec![file_node("1.txt"), file_node("2.txt"), file_node("3.txt")],
+                ),
+            ],
+        );
+
+        let config = Config {
+            sort: SortKey::Count,
+            ..Config::default()
+        };
+        sort_tree(&mut tree, &config);
+
+        let names = child_names(&tree);
+        assert_eq!(names, vec!["big", "small", "a.txt"]);
+    }
+
+    #[test]
+    fn sort_dirs_and_files_with_independent_keys() {
+        let mut tree = dir_node(
+            ".",
+            vec![
+                sized_file_node("small.txt", 10),
+                dir_node("zeta", vec![]),
+                sized_file_node("big.txt", 300),
+                dir_node("alpha", vec![]),
+                sized_file_node("mid.txt", 100),
+            ],
+        );
+
+        let config = Config {
+            dir_sort: Some(SortKey::Name),
+            file_sort: Some(SortKey::Size),
+            ..Config::default()
+        };
+        sort_tree(&mut tree, &config);
+
+        assert_eq!(
+            child_names(&tree),
+            vec!["alpha", "zeta", "small.txt", "mid.txt", "big.txt"]
+        );
+    }
+
+    #[test]
+    fn sort_case_strict_orders_uppercase_first() {
+        use crate::config::SortCase;
+
+        let build = || {
+            dir_node(
+                ".",
+                vec![file_node("readme"), file_node("BUILD"), file_node("Zebra")],
+            )
+        };
+
+        let mut folded = build();
+        sort_tree(&mut folded, &Config::default());
+        assert_eq!(child_names(&folded), vec!["BUILD", "readme", "Zebra"]);
+
+        let mut strict = build();
+        sort_tree(
+            &mut strict,
+            &Config {
+                sort_case: SortCase::Strict,
+                ..Config::default()
+            },
+        );
+        assert_eq!(child_names(&strict), vec!["BUILD", "Zebra", "readme"]);
+    }
+
+    #[test]
+    fn sort_name_path_breaks_name_ties_by_path() {
+        use std::path::PathBuf;
+
+        let mut a = file_node("mod.rs");
+        a.path = PathBuf::from("zeta/mod.rs");
+        let mut b = file_node("mod.rs");
+        b.path = PathBuf::from("alpha/mod.rs");
+        let mut tree = dir_node(".", vec![a, b]);
+
+        let config = Config {
+            sort: SortKey::NamePath,
+            ..Config::default()
+        };
+        sort_tree(&mut tree, &config);
+
+        let paths: Vec<_> = tree
+            .children
+            .iter()
+            .map(|c| c.path.display().to_string())
+            .collect();
+        assert_eq!(paths, vec!["alpha/mod.rs", "zeta/mod.rs"]);
+    }
+
+    #[test]
+    fn sort_first_seen_is_reproducible_byte_for_byte() {
+        use crate::render::render;
+        use crate::walk::walk;
+        use std::fs;
+        use tempfile::tempdir;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+        fs::create_dir(path.join("sub")).unwrap();
+        fs::write(path.join("b.txt"), "").unwrap();
+        fs::write(path.join("a.txt"), "").unwrap();
+        fs::write(path.join("sub/c.txt"), "").unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            sort: SortKey::FirstSeen,
+            ..Config::default()
+        };
+
+        let render_once = || {
+            let mut tree = walk(&config).unwrap().root;
+            sort_tree(&mut tree, &config);
+            let mut buf = Vec::new();
+            render(&mut buf, &tree, &config).unwrap();
+            buf
+        };
+
+        assert_eq!(render_once(), render_once());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sort_mode_orders_permissive_files_first() {
+        use crate::walk::walk;
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+        use tempfile::tempdir;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+        fs::write(path.join("open.sh"), "").unwrap();
+        fs::write(path.join("closed.txt"), "").unwrap();
+        fs::set_permissions(path.join("open.sh"), fs::Permissions::from_mode(0o777)).unwrap();
+        fs::set_permissions(path.join("closed.txt"), fs::Permissions::from_mode(0o644)).unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            sort: SortKey::Mode,
+            ..Config::default()
+        };
+        let mut tree = walk(&config).unwrap().root;
+        sort_tree(&mut tree, &config);
+
+        assert_eq!(child_names(&tree), vec!["open.sh", "closed.txt"]);
+    }
+
+    #[test]
+    fn sort_nulls_first_surfaces_unreadable_entries() {
+        let build = || {
+            dir_node(
+                ".",
+                vec![
+                    sized_file_node("big.txt", 100),
+                    null_size_file_node("broken.txt"),
+                    sized_file_node("small.txt", 1),
+                ],
+            )
+        };
+
+        let mut first = build();
+        sort_tree(
+            &mut first,
+            &Config {
+                sort: SortKey::Size,
+                sort_nulls: SortNulls::First,
+                ..Config::default()
+            },
+        );
+        assert_eq!(
+            child_names(&first),
+            vec!["broken.txt", "small.txt", "big.txt"]
+        );
+
+        let mut last = build();
+        sort_tree(
+            &mut last,
+            &Config {
+                sort: SortKey::Size,
+                ..Config::default()
+            },
+        );
+        assert_eq!(
+            child_names(&last),
+            vec!["small.txt", "big.txt", "broken.txt"]
+        );
+    }
+
+    #[test]
+    fn sort_random_same_seed_is_reproducible() {
+        let build = || {
+            dir_node(
+                ".",
+                vec![
+                    file_node("a.txt"),
+                    file_node("b.txt"),
+                    file_node("c.txt"),
+                    file_node("d.txt"),
+                    file_node("e.txt"),
+                ],
+            )
+        };
+        let config = Config {
+            sort: SortKey::Random,
+            seed: Some(42),
+            ..Config::default()
+        };
+
+        let mut first = build();
+        let mut second = build();
+        sort_tree(&mut first, &config);
+        sort_tree(&mut second, &config);
+
+        assert_eq!(child_names(&first), child_names(&second));
+    }
+
+    #[test]
+    fn sort_random_different_seeds_differ() {
+        let build = || {
+            dir_node(
+                ".",
+                vec![
+                    file_node("a.txt"),
+                    file_node("b.txt"),
+                    file_node("c.txt"),
+                    file_node("d.txt"),
+                    file_node("e.txt"),
+                    file_node("f.txt"),
+                    file_node("g.txt"),
+                    file_node("h.txt"),
+                ],
+            )
+        };
+
+        let mut first = build();
+        let mut second = build();
+        sort_tree(
+            &mut first,
+            &Config {
+                sort: SortKey::Random,
+                seed: Some(1),
+                ..Config::default()
+            },
+        );
+        sort_tree(
+            &mut second,
+            &Config {
+                sort: SortKey::Random,
+                seed: Some(2),
+                ..Config::default()
+            },
+        );
+
+        assert_ne!(child_names(&first), child_names(&second));
+    }
+
+    #[test]
+    fn sort_name_dirs_first_groups_directories() {
+        let mut tree = dir_node(
+            ".",
+            vec![
+                file_node("a.txt"),
+                dir_node("zeta", vec![]),
+                file_node("b.txt"),
+            ],
+        );
+
+        let config = Config {
+            dirs_first: true,
+            ..Config::default()
+        };
+        sort_tree(&mut tree, &config);
+
+        assert_eq!(child_names(&tree), vec!["zeta", "a.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn sort_natural_ci_orders_numbers_ignoring_case() {
+        let mut tree = dir_node(
+            ".",
+            vec![file_node("IMG10"), file_node("Img1"), file_node("img2")],
+        );
+
+        let config = Config {
+            sort: SortKey::NaturalCi,
+            ..Config::default()
+        };
+        sort_tree(&mut tree, &config);
+
+        assert_eq!(child_names(&tree), vec!["Img1", "img2", "IMG10"]);
+    }
+
+    #[test]
+    fn sort_children_size_orders_heavier_directories_first() {
+        use crate::stats::aggregate_sizes;
+
+        let mut tree = dir_node(
+            ".",
+            vec![
+                dir_node("aaa-small", vec![sized_file_node("x.txt", 10)]),
+                dir_node("zzz-big", vec![sized_file_node("y.txt", 5000)]),
+            ],
+        );
+        aggregate_sizes(&mut tree);
+
+        let config = Config {
+            sort: SortKey::ChildrenSize,
+            ..Config::default()
+        };
+        sort_tree(&mut tree, &config);
+
+        assert_eq!(child_names(&tree), vec!["zzz-big", "aaa-small"]);
+    }
+
+    #[test]
+    fn sort_files_before_subdirs_overrides_dirsfirst() {
+        let mut tree = dir_node(
+            ".",
+            vec![
+                dir_node("adir", vec![]),
+                file_node("z.txt"),
+                file_node("a.txt"),
+                dir_node("zdir", vec![]),
+            ],
+        );
+
+        let config = Config {
+            dirs_first: true,
+            files_before_subdirs: true,
+            ..Config::default()
+        };
+        sort_tree(&mut tree, &config);
+
+        assert_eq!(child_names(&tree), vec!["a.txt", "z.txt", "adir", "zdir"]);
+    }
+
+    #[test]
+    fn sort_order_ignores_lc_collate() {
+        // ロケールを変えてもコードポイント順のまま変わらないことを確認する
+        unsafe { std::env::set_var("LC_COLLATE", "de_DE.UTF-8") };
+
+        let mut tree = dir_node(
+            ".",
+            vec![file_node("zebra"), file_node("Apfel"), file_node("apfel")],
+        );
+        let config = Config {
+            sort_case: crate::config::SortCase::Strict,
+            ..Config::default()
+        };
+        sort_tree(&mut tree, &config);
+
+        assert_eq!(child_names(&tree), vec!["Apfel", "apfel", "zebra"]);
+    }
+
+    #[test]
+    fn sort_flat_orders_entire_list_by_size() {
+        let tree = dir_node(
+            ".",
+            vec![
+                dir_node("a", vec![sized_file_node("big.txt", 500)]),
+                dir_node("b", vec![sized_file_node("small.txt", 1)]),
+            ],
+        );
+        let config = Config {
+            sort: SortKey::Size,
+            ..Config::default()
+        };
+        let mut entries = crate::walk::flatten_tree(&tree);
+        sort_flat(&mut entries, &config);
+        let files: Vec<&str> = entries
+            .iter()
+            .filter(|(_, n)| n.kind == EntryKind::File)
+            .map(|(p, _)| p.as_str())
+            .collect();
+        assert_eq!(files, ["b/small.txt", "a/big.txt"]);
+    }
+
+    #[test]
+    fn sort_type_then_name_groups_dirs_links_files() {
+        let symlink_node = |name: &str| {
+            let mut node = file_node(name);
+            node.kind = EntryKind::Symlink;
+            node
+        };
+        let mut tree = dir_node(
+            ".",
+            vec![
+                file_node("b.txt"),
+                symlink_node("z-link"),
+                dir_node("z-dir", vec![]),
+                file_node("a.txt"),
+                dir_node("a-dir", vec![]),
+                symlink_node("a-link"),
+            ],
+        );
+        let config = Config {
+            sort: SortKey::TypeThenName,
+            ..Config::default()
+        };
+        sort_tree(&mut tree, &config);
+
+        assert_eq!(
+            child_names(&tree),
+            vec!["a-dir", "z-dir", "a-link", "z-link", "a.txt", "b.txt"]
+        );
+    }
+}
@@ -0,0 +1,577 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::error::AppError;
+use crate::walk::{read_directory, validate_path, EntryKind, Node};
+
+/// レンダリングなしの走査で集計する統計情報
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WalkStats {
+    pub total_entries: usize,
+    pub total_dirs: usize,
+    pub total_files: usize,
+    pub total_bytes: u64,
+    pub max_depth: usize,
+}
+
+/// ツリーを走査して統計だけを返す。ベンチマーク等、描画が不要な用途向け
+pub fn walk_stats<P: AsRef<Path>>(path: P) -> Result<WalkStats, AppError> {
+    validate_path(&path)?;
+
+    let mut stats = WalkStats::default();
+    visit(path.as_ref(), 1, &mut stats)?;
+    Ok(stats)
+}
+
+fn visit(dir: &Path, depth: usize, stats: &mut WalkStats) -> Result<(), AppError> {
+    for entry in read_directory(dir)? {
+        let metadata = entry.metadata()?;
+        stats.total_entries += 1;
+        stats.max_depth = stats.max_depth.max(depth);
+
+        if metadata.is_dir() {
+            stats.total_dirs += 1;
+            visit(&entry.path(), depth + 1, stats)?;
+        } else {
+            stats.total_files += 1;
+            stats.total_bytes += metadata.len();
+        }
+    }
+    Ok(())
+}
+
+/// `--unique-names` 用: 同じファイル名が複数の場所にあるグループを集める。
+/// 1 箇所にしかない名前は含めない
+pub fn duplicate_name_groups(root: &Node) -> Vec<(String, Vec<PathBuf>)> {
+    fn collect(node: &Node, map: &mut HashMap<String, Vec<PathBuf>>) {
+        for child in &node.children {
+            if child.kind == EntryKind::Marker {
+                continue;
+            }
+            map.entry(child.name.clone())
+                .or_default()
+                .push(child.path.clone());
+            collect(child, map);
+        }
+    }
+
+    let mut map = HashMap::new();
+    collect(root, &mut map);
+
+    let mut groups: Vec<_> = map
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .collect();
+    groups.sort_by(|a, b| a.0.cmp(&b.0));
+    groups
+}
+
+/// `--partition-by-size` の既定バケツ境界 (1K / 1M)
+const SIZE_BUCKETS: [u64; 2] = [1024, 1024 * 1024];
+
+/// ファイルをサイズ帯ごとに数える (`--partition-by-size`)。
+/// 戻り値は `[<1K, 1K-1M, >1M]` の順
+pub fn partition_by_size(root: &Node) -> [usize; 3] {
+    fn visit(node: &Node, counts: &mut [usize; 3]) {
+        for child in &node.children {
+            match child.kind {
+                EntryKind::Dir => visit(child, counts),
+                EntryKind::File | EntryKind::Symlink => {
+                    let size = child.size.unwrap_or_default();
+                    let bucket = SIZE_BUCKETS.iter().position(|&b| size < b).unwrap_or(2);
+                    counts[bucket] += 1;
+                }
+                EntryKind::Marker => {}
+            }
+        }
+    }
+
+    let mut counts = [0; 3];
+    visit(root, &mut counts);
+    counts
+}
+
+/// サイズ分布レポートを表示用に整形する
+pub fn format_size_partition(counts: &[usize; 3]) -> String {
+    format!(
+        "<1K: {}, 1K-1M: {}, >1M: {}",
+        counts[0], counts[1], counts[2]
+    )
+}
+
+/// 走査済みツリーからファイル数・ディレクトリ数・合計バイト数を集計する
+/// (`--show-root-stats`)。ルート自身とマーカーは数えない
+pub fn tree_totals(root: &Node) -> (usize, usize, u64) {
+    fn visit(node: &Node, files: &mut usize, dirs: &mut usize, bytes: &mut u64) {
+        for child in &node.children {
+            match child.kind {
+                EntryKind::Marker => continue,
+                EntryKind::Dir => *dirs += 1,
+                EntryKind::File | EntryKind::Symlink => {
+                    *files += 1;
+                    *bytes += child.size.unwrap_or_default();
+                }
+            }
+            visit(child, files, dirs, bytes);
+        }
+    }
+
+    let (mut files, mut dirs, mut bytes) = (0, 0, 0);
+    visit(root, &mut files, &mut dirs, &mut bytes);
+    (files, dirs, bytes)
+}
+
+/// `--report-deepest` 用: ツリー中で最も深いエントリの相対パスと深さを
+/// 返す (同率なら最初に見つかったもの)。空のツリーでは None
+pub fn deepest_path(root: &Node) -> Option<(String, usize)> {
+    fn visit(node: &Node, prefix: &str, depth: usize, best: &mut Option<(String, usize)>) {
+        for child in &node.children {
+            if child.kind == EntryKind::Marker {
+                continue;
+            }
+            let path = if prefix.is_empty() {
+                child.name.clone()
+            } else {
+                format!("{}/{}", prefix, child.name)
+            };
+            if best.as_ref().is_none_or(|(_, d)| depth > *d) {
+                *best = Some((path.clone(), depth));
+            }
+            visit(child, &path, depth + 1, best);
+        }
+    }
+
+    let mut best = None;
+    visit(root, "", 1, &mut best);
+    best
+}
+
+pub fn format_deepest(path: &str, depth: usize) -> String {
+    format!("Deepest: {} (depth {})", path, depth)
+}
+
+/// `--count-hidden-separately` 用: 表示されたエントリのうちドットで始まる
+/// 名前のものを数える (ルート自身とマーカーは除く)
+pub fn hidden_count(root: &Node) -> usize {
+    fn visit(node: &Node, hidden: &mut usize) {
+        for child in &node.children {
+            if child.kind == EntryKind::Marker {
+                continue;
+            }
+            if child.name.starts_with('.') {
+                *hidden += 1;
+            }
+            visit(child, hidden);
+        }
+    }
+
+    let mut hidden = 0;
+    visit(root, &mut hidden);
+    hidden
+}
+
+/// `--count-hidden-separately` の合計行。隠しエントリの数を括弧で添える
+pub fn format_summary_with_hidden(root: &Node) -> String {
+    let (files, dirs, _) = tree_totals(root);
+    format!(
+        "{} directories, {} files ({} hidden)",
+        dirs,
+        files,
+        hidden_count(root)
+    )
+}
+
+/// `--summary-json` 用: ツリー全体の合計を 1 行の JSON で返す。
+/// バイト数は `--du` でサイズを集計している場合のみ含める
+pub fn format_summary_json(root: &Node, include_bytes: bool) -> String {
+    let (files, dirs, bytes) = tree_totals(root);
+    if include_bytes {
+        format!(
+            "{{\"directories\":{},\"files\":{},\"bytes\":{}}}",
+            dirs, files, bytes
+        )
+    } else {
+        format!("{{\"directories\":{},\"files\":{}}}", dirs, files)
+    }
+}
+
+/// 深さごとのエントリ数 (`--count-by-depth-json`)。ルートは深さ 0
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DepthCount {
+    pub depth: usize,
+    pub dirs: usize,
+    pub files: usize,
+}
+
+/// ツリーを深さごとにバケツ分けして数える。マーカーは数えない
+pub fn count_by_depth(root: &Node) -> Vec<DepthCount> {
+    fn visit(node: &Node, depth: usize, counts: &mut Vec<DepthCount>) {
+        if node.kind == EntryKind::Marker {
+            return;
+        }
+        if counts.len() <= depth {
+            counts.push(DepthCount {
+                depth,
+                ..DepthCount::default()
+            });
+        }
+        if node.kind == EntryKind::Dir {
+            counts[depth].dirs += 1;
+        } else {
+            counts[depth].files += 1;
+        }
+        for child in &node.children {
+            visit(child, depth + 1, counts);
+        }
+    }
+
+    let mut counts = Vec::new();
+    visit(root, 0, &mut counts);
+    counts
+}
+
+/// 深さレポートを JSON 配列として整形する
+pub fn format_count_by_depth_json(counts: &[DepthCount]) -> String {
+    let objects: Vec<String> = counts
+        .iter()
+        .map(|c| {
+            format!(
+                "{{\"depth\":{},\"dirs\":{},\"files\":{}}}",
+                c.depth, c.dirs, c.files
+            )
+        })
+        .collect();
+    format!("[{}]", objects.join(","))
+}
+
+/// ディレクトリの `size` に子孫ファイルの合計バイト数を畳み込む
+/// (`--du`)。戻り値はそのノード以下の総バイト数
+pub fn aggregate_sizes(node: &mut Node) -> u64 {
+    if node.kind == EntryKind::Dir {
+        let total = node.children.iter_mut().map(aggregate_sizes).sum();
+        node.size = Some(total);
+    }
+    node.size.unwrap_or_default()
+}
+
+/// `--report-empty-dirs` 用: (フィルタ後の) 子が 1 つもないディレクトリを
+/// 集める。collapse されたディレクトリは中身が不明なので除外する
+pub fn empty_dirs(root: &Node) -> Vec<PathBuf> {
+    fn collect(node: &Node, out: &mut Vec<PathBuf>) {
+        for child in &node.children {
+            if child.kind != EntryKind::Dir {
+                continue;
+            }
+            if child.children.is_empty() && child.note.as_deref() != Some("[collapsed]") {
+                out.push(child.path.clone());
+            }
+            collect(child, out);
+        }
+    }
+
+    let mut out = Vec::new();
+    collect(root, &mut out);
+    out
+}
+
+/// 空ディレクトリレポートを表示用に整形する
+pub fn format_empty_dirs(dirs: &[PathBuf]) -> String {
+    let mut out = String::new();
+    for dir in dirs {
+        out.push_str(&format!("empty: {}\n", dir.display()));
+    }
+    out
+}
+
+/// `--show-link-count-summary` の集計結果
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LinkSummary {
+    pub total: usize,
+    pub broken: usize,
+    pub escaping: usize,
+}
+
+/// `--show-link-count-summary` 用: ツリー中のシンボリックリンクを数え、
+/// リンク先の存在とルート内への収まりを検査する。リンク先の解決は
+/// 正規化したパスを `strip_prefix` でルートと突き合わせて判定する
+pub fn link_summary(root: &Node) -> LinkSummary {
+    fn collect(node: &Node, root_path: &Path, summary: &mut LinkSummary) {
+        if node.kind == EntryKind::Symlink {
+            summary.total += 1;
+            match std::fs::canonicalize(&node.path) {
+                Ok(target) if target.strip_prefix(root_path).is_err() => summary.escaping += 1,
+                Ok(_) => {}
+                Err(_) => summary.broken += 1,
+            }
+        }
+        for child in &node.children {
+            collect(child, root_path, summary);
+        }
+    }
+
+    let mut summary = LinkSummary::default();
+    collect(root, &root.path, &mut summary);
+    summary
+}
+
+/// リンク集計レポートを表示用に整形する
+pub fn format_link_summary(summary: &LinkSummary) -> String {
+    format!(
+        "symlinks: {} total, {} broken, {} escaping\n",
+        summary.total, summary.broken, summary.escaping
+    )
+}
+
+/// 重複名レポートを表示用に整形する
+pub fn format_duplicate_names(groups: &[(String, Vec<PathBuf>)]) -> String {
+    let mut out = String::new();
+    for (name, paths) in groups {
+        out.push_str(&format!("{}:\n", name));
+        for path in paths {
+            out.push_str(&format!("  {}\n", path.display()));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn walk_stats_counts_known_tree() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        fs::write(path.join("a.txt"), vec![0u8; 100]).unwrap();
+        fs::create_dir_all(path.join("sub/nested")).unwrap();
+        fs::write(path.join("sub/b.txt"), vec![0u8; 50]).unwrap();
+        fs::write(path.join("sub/nested/c.txt"), vec![0u8; 25]).unwrap();
+
+        let stats = walk_stats(path).unwrap();
+
+        assert_eq!(stats.total_entries, 5);
+        assert_eq!(stats.total_dirs, 2);
+        assert_eq!(stats.total_files, 3);
+        assert_eq!(stats.total_bytes, 175);
+        assert_eq!(stats.max_depth, 3);
+    }
+
+    #[test]
+    fn duplicate_name_groups_reports_same_name_in_different_dirs() {
+        use crate::config::Config;
+        use crate::walk::walk;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        fs::create_dir_all(path.join("a")).unwrap();
+        fs::create_dir_all(path.join("b")).unwrap();
+        fs::write(path.join("a/mod.rs"), "").unwrap();
+        fs::write(path.join("b/mod.rs"), "").unwrap();
+        fs::write(path.join("a/unique.rs"), "").unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+        let groups = duplicate_name_groups(&tree);
+
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].0, "mod.rs");
+        assert_eq!(groups[0].1.len(), 2);
+
+        let report = format_duplicate_names(&groups);
+        assert!(report.starts_with("mod.rs:\n"));
+        assert!(report.contains("a/mod.rs"));
+        assert!(report.contains("b/mod.rs"));
+    }
+
+    #[test]
+    fn partition_by_size_counts_files_per_bucket() {
+        use crate::walk::test_util::*;
+
+        let tree = dir_node(
+            ".",
+            vec![
+                sized_file_node("tiny.txt", 10),
+                sized_file_node("small.txt", 1023),
+                dir_node("sub", vec![sized_file_node("mid.txt", 2048)]),
+                sized_file_node("huge.bin", 5 * 1024 * 1024),
+            ],
+        );
+
+        let counts = partition_by_size(&tree);
+
+        assert_eq!(counts, [2, 1, 1]);
+        assert_eq!(format_size_partition(&counts), "<1K: 2, 1K-1M: 1, >1M: 1");
+    }
+
+    #[test]
+    fn count_by_depth_buckets_each_level() {
+        use crate::walk::test_util::*;
+
+        let tree = dir_node(
+            ".",
+            vec![
+                file_node("a.txt"),
+                dir_node("sub", vec![file_node("b.txt"), file_node("c.txt")]),
+            ],
+        );
+
+        let counts = count_by_depth(&tree);
+
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts[0], DepthCount { depth: 0, dirs: 1, files: 0 });
+        assert_eq!(counts[1], DepthCount { depth: 1, dirs: 1, files: 1 });
+        assert_eq!(counts[2], DepthCount { depth: 2, dirs: 0, files: 2 });
+
+        assert_eq!(
+            format_count_by_depth_json(&counts),
+            concat!(
+                "[{\"depth\":0,\"dirs\":1,\"files\":0},",
+                "{\"depth\":1,\"dirs\":1,\"files\":1},",
+                "{\"depth\":2,\"dirs\":0,\"files\":2}]"
+            )
+        );
+    }
+
+    #[test]
+    fn aggregate_sizes_folds_descendant_bytes_into_dirs() {
+        use crate::walk::test_util::*;
+
+        let mut tree = dir_node(
+            ".",
+            vec![
+                sized_file_node("a.txt", 100),
+                dir_node("sub", vec![sized_file_node("b.txt", 50)]),
+            ],
+        );
+
+        assert_eq!(aggregate_sizes(&mut tree), 150);
+        assert_eq!(tree.size, Some(150));
+        assert_eq!(tree.children[1].size, Some(50));
+    }
+
+    #[test]
+    fn empty_dirs_lists_nested_and_top_level() {
+        use crate::config::Config;
+        use crate::walk::walk;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        fs::create_dir(path.join("empty_top")).unwrap();
+        fs::create_dir_all(path.join("full/empty_nested")).unwrap();
+        fs::write(path.join("full/a.txt"), "").unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+        let dirs = empty_dirs(&tree);
+
+        assert_eq!(dirs.len(), 2);
+        assert!(dirs.iter().any(|d| d.ends_with("empty_top")));
+        assert!(dirs.iter().any(|d| d.ends_with("full/empty_nested")));
+
+        let report = format_empty_dirs(&dirs);
+        assert!(report.contains("empty: "));
+        assert!(report.contains("empty_nested"));
+    }
+
+    #[test]
+    fn walk_stats_missing_path_returns_err() {
+        let dir = tempdir().unwrap();
+
+        let result = walk_stats(dir.path().join("missing"));
+        assert!(matches!(result, Err(AppError::PathNotFound(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn link_summary_counts_valid_broken_and_escaping() {
+        use crate::walk::test_util::*;
+        use std::os::unix::fs::symlink;
+
+        let outside = tempfile::tempdir().unwrap();
+        write_file(&outside.path().join("target.txt"), 1);
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        write_file(&root.join("inner.txt"), 1);
+        symlink(root.join("inner.txt"), root.join("valid")).unwrap();
+        symlink(root.join("missing"), root.join("broken")).unwrap();
+        symlink(outside.path().join("target.txt"), root.join("escape")).unwrap();
+
+        let mut node = dir_node("root", Vec::new());
+        node.path = root.clone();
+        for name in ["valid", "broken", "escape"] {
+            let mut child = file_node(name);
+            child.kind = EntryKind::Symlink;
+            child.path = root.join(name);
+            node.children.push(child);
+        }
+
+        let summary = link_summary(&node);
+        assert_eq!(summary.total, 3);
+        assert_eq!(summary.broken, 1);
+        assert_eq!(summary.escaping, 1);
+    }
+
+    #[test]
+    fn format_summary_json_counts_tree_and_gates_bytes() {
+        use crate::walk::test_util::*;
+        let mut root = dir_node("root", vec![dir_node("sub", vec![file_node("b.txt")])]);
+        root.children.push(file_node("a.txt"));
+        root.children[0].children[0].size = Some(10);
+        root.children[1].size = Some(5);
+        assert_eq!(
+            format_summary_json(&root, false),
+            "{\"directories\":1,\"files\":2}"
+        );
+        assert_eq!(
+            format_summary_json(&root, true),
+            "{\"directories\":1,\"files\":2,\"bytes\":15}"
+        );
+    }
+
+    #[test]
+    fn deepest_path_reports_first_deepest_entry() {
+        use crate::walk::test_util::*;
+        let tree = dir_node(
+            ".",
+            vec![
+                file_node("top.txt"),
+                dir_node(
+                    "a",
+                    vec![dir_node("b", vec![file_node("deep.txt"), file_node("tie.txt")])],
+                ),
+            ],
+        );
+        let (path, depth) = deepest_path(&tree).unwrap();
+        assert_eq!(path, "a/b/deep.txt");
+        assert_eq!(depth, 3);
+        assert_eq!(format_deepest(&path, depth), "Deepest: a/b/deep.txt (depth 3)");
+    }
+
+    #[test]
+    fn format_summary_with_hidden_counts_dotfiles() {
+        use crate::walk::test_util::*;
+        let tree = dir_node(
+            ".",
+            vec![
+                file_node(".env"),
+                dir_node(".git", vec![file_node("HEAD")]),
+                file_node("main.rs"),
+            ],
+        );
+        assert_eq!(
+            format_summary_with_hidden(&tree),
+            "1 directories, 3 files (2 hidden)"
+        );
+    }
+}
@@ -0,0 +1,337 @@
+use crate::error::AppError;
+
+/// `1K` のようなサイズ表記をバイト数に変換する (K/M/G/T, 1K = 1024)
+pub fn parse_size(s: &str) -> Result<u64, AppError> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err(AppError::InvalidArgs);
+    }
+
+    let (num, unit) = match s.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((idx, _)) => s.split_at(idx),
+        None => (s, ""),
+    };
+
+    let value: u64 = num.parse().map_err(|_| AppError::InvalidArgs)?;
+    let factor: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" => 1024,
+        "M" => 1024 * 1024,
+        "G" => 1024 * 1024 * 1024,
+        "T" => 1024u64.pow(4),
+        _ => return Err(AppError::InvalidArgs),
+    };
+
+    value.checked_mul(factor).ok_or(AppError::InvalidArgs)
+}
+
+/// `*` と `?` をサポートする簡易グロブマッチ
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    fn matches(p: &[char], t: &[char]) -> bool {
+        match (p.first(), t.first()) {
+            (None, None) => true,
+            (Some('*'), _) => {
+                matches(&p[1..], t) || (!t.is_empty() && matches(p, &t[1..]))
+            }
+            (Some('?'), Some(_)) => matches(&p[1..], &t[1..]),
+            (Some(pc), Some(tc)) if pc == tc => matches(&p[1..], &t[1..]),
+            _ => false,
+        }
+    }
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    matches(&p, &t)
+}
+
+/// `--deterministic-timestamps` 用: 基準時刻からの相対秒を `+3600s` /
+/// `-120s` の形式にする
+pub fn format_relative_secs(
+    time: std::time::SystemTime,
+    reference: std::time::SystemTime,
+) -> String {
+    match time.duration_since(reference) {
+        Ok(d) => format!("+{}s", d.as_secs()),
+        Err(e) => format!("-{}s", e.duration().as_secs()),
+    }
+}
+
+/// バイト数を `3.2M` のような人間向け表記にする (1K = 1024)
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut value = bytes as f64;
+    let mut unit = "";
+    for u in UNITS {
+        value /= 1024.0;
+        unit = u;
+        if value < 1024.0 {
+            break;
+        }
+    }
+    format!("{:.1}{}", value, unit)
+}
+
+/// mtime 表示用に epoch 秒から `YYYY-MM-DD HH:MM` (UTC) を組み立てる。
+/// 変換はグレゴリオ暦の civil-from-days アルゴリズムによる
+pub fn format_timestamp(time: std::time::SystemTime) -> String {
+    let secs = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let days = secs.div_euclid(86_400);
+    let rem = secs.rem_euclid(86_400);
+
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let year = yoe + era * 400;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = if month <= 2 { year + 1 } else { year };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60
+    )
+}
+
+/// `--profile` 用: フェーズ名と経過時間を stderr 向けの 1 行にする
+pub fn format_profile(phase: &str, elapsed: std::time::Duration) -> String {
+    format!("profile: {} {:.1}ms", phase, elapsed.as_secs_f64() * 1000.0)
+}
+
+/// `--trim-common-prefix` 用: 複数ルートに共通するディレクトリ接頭辞を
+/// コンポーネント単位で求める。共通部分がなければ `None`
+pub fn common_dir_prefix(paths: &[std::path::PathBuf]) -> Option<std::path::PathBuf> {
+    let first = paths.first()?;
+    let mut prefix: Vec<std::path::Component> = first.components().collect();
+    for path in &paths[1..] {
+        let components: Vec<_> = path.components().collect();
+        let shared = prefix
+            .iter()
+            .zip(&components)
+            .take_while(|(a, b)| a == b)
+            .count();
+        prefix.truncate(shared);
+    }
+    if prefix.is_empty() {
+        None
+    } else {
+        Some(prefix.iter().collect())
+    }
+}
+
+/// `--pager` 用: ページャを起動して stdin をパイプで受け取れる状態で返す。
+/// `$PAGER` の引数込みの文字列を解釈できるようシェル経由で起動する
+pub fn spawn_pager(command: &str) -> Option<std::process::Child> {
+    std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .ok()
+}
+
+/// RFC 4648 の base64 エンコード (パディングあり)。外部クレートを増やさない
+/// ため手書きで持つ
+pub fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3f] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// RFC 4648 の base32 エンコード (パディングあり)
+pub fn base32_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+    let mut out = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    for chunk in bytes.chunks(5) {
+        let mut b = [0u8; 5];
+        b[..chunk.len()].copy_from_slice(chunk);
+        let n = u64::from_be_bytes([0, 0, 0, b[0], b[1], b[2], b[3], b[4]]);
+        // チャンク長ごとの有効出力文字数 (残りはパディング)
+        let valid = [0, 2, 4, 5, 7, 8][chunk.len()];
+        for i in 0..8 {
+            if i < valid {
+                out.push(ALPHABET[(n >> (35 - 5 * i)) as usize & 0x1f] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// `--relative-targets` 用: base から target への相対パスを `..` 込みで
+/// 組み立てる。共通の接頭辞まで遡ってから残りを連結する
+pub fn relative_to(target: &std::path::Path, base: &std::path::Path) -> std::path::PathBuf {
+    let target_parts: Vec<_> = target.components().collect();
+    let base_parts: Vec<_> = base.components().collect();
+    let common = target_parts
+        .iter()
+        .zip(&base_parts)
+        .take_while(|(t, b)| t == b)
+        .count();
+    let mut out = std::path::PathBuf::new();
+    for _ in common..base_parts.len() {
+        out.push("..");
+    }
+    for part in &target_parts[common..] {
+        out.push(part);
+    }
+    if out.as_os_str().is_empty() {
+        out.push(".");
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_size_plain_number_returns_bytes() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+    }
+
+    #[test]
+    fn parse_size_with_suffix_returns_multiplied() {
+        assert_eq!(parse_size("1K").unwrap(), 1024);
+        assert_eq!(parse_size("2M").unwrap(), 2 * 1024 * 1024);
+        assert_eq!(parse_size("1G").unwrap(), 1024 * 1024 * 1024);
+        assert_eq!(parse_size("1g").unwrap(), 1024 * 1024 * 1024);
+    }
+
+    #[test]
+    fn parse_size_invalid_returns_err() {
+        assert!(matches!(parse_size(""), Err(AppError::InvalidArgs)));
+        assert!(matches!(parse_size("abc"), Err(AppError::InvalidArgs)));
+        assert!(matches!(parse_size("1X"), Err(AppError::InvalidArgs)));
+    }
+
+    #[test]
+    fn format_relative_secs_signs_both_directions() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let reference = UNIX_EPOCH + Duration::from_secs(1_000);
+        assert_eq!(
+            format_relative_secs(reference + Duration::from_secs(3600), reference),
+            "+3600s"
+        );
+        assert_eq!(
+            format_relative_secs(reference - Duration::from_secs(120), reference),
+            "-120s"
+        );
+        assert_eq!(format_relative_secs(reference, reference), "+0s");
+    }
+
+    #[test]
+    fn human_size_picks_fitting_unit() {
+        assert_eq!(human_size(512), "512B");
+        assert_eq!(human_size(1024), "1.0K");
+        assert_eq!(human_size(3 * 1024 * 1024 + 200 * 1024), "3.2M");
+    }
+
+    #[test]
+    fn format_timestamp_known_instants() {
+        use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01 00:00");
+        // 2026-08-31 12:34 UTC
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1_788_179_640);
+        assert_eq!(format_timestamp(t), "2026-08-31 12:34");
+    }
+
+    #[test]
+    fn format_profile_contains_phase_label() {
+        let line = format_profile("walk", std::time::Duration::from_millis(12));
+        assert!(line.starts_with("profile: walk "));
+        assert!(line.ends_with("ms"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn spawn_pager_delivers_output_to_child_stdin() {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        let sink = dir.path().join("sink");
+        let mut child = spawn_pager(&format!("cat > {}", sink.display())).unwrap();
+
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(b"tree output\n").unwrap();
+        drop(stdin);
+        child.wait().unwrap();
+
+        assert_eq!(std::fs::read_to_string(&sink).unwrap(), "tree output\n");
+    }
+
+    #[test]
+    fn common_dir_prefix_finds_shared_components() {
+        use std::path::PathBuf;
+
+        let roots = vec![PathBuf::from("/a/b/r1"), PathBuf::from("/a/b/r2")];
+        assert_eq!(common_dir_prefix(&roots), Some(PathBuf::from("/a/b")));
+
+        let disjoint = vec![PathBuf::from("/a/x"), PathBuf::from("b/y")];
+        assert_eq!(common_dir_prefix(&disjoint), None);
+    }
+
+    #[test]
+    fn base64_encode_known_vectors() {
+        assert_eq!(base64_encode(b"hello.txt"), "aGVsbG8udHh0");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b""), "");
+    }
+
+    #[test]
+    fn base32_encode_known_vectors() {
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI======");
+        assert_eq!(base32_encode(b"f"), "MY======");
+        assert_eq!(base32_encode(b""), "");
+    }
+
+    #[test]
+    fn glob_match_star_and_question() {
+        assert!(glob_match("*.rs", "main.rs"));
+        assert!(glob_match("file?.txt", "file1.txt"));
+        assert!(!glob_match("*.rs", "main.rb"));
+        assert!(glob_match("target", "target"));
+        assert!(!glob_match("target", "targets"));
+    }
+
+    #[test]
+    fn relative_to_walks_up_to_common_prefix() {
+        use std::path::Path;
+
+        assert_eq!(
+            relative_to(Path::new("/a/b/sub/t.txt"), Path::new("/a/b")),
+            Path::new("sub/t.txt")
+        );
+        assert_eq!(
+            relative_to(Path::new("/a/x/t.txt"), Path::new("/a/b/c")),
+            Path::new("../../x/t.txt")
+        );
+        assert_eq!(relative_to(Path::new("/a/b"), Path::new("/a/b")), Path::new("."));
+    }
+}
@@ -0,0 +1,2548 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, OnError, SymlinkPolicy, TimeKind};
+use crate::error::AppError;
+use crate::filter::eval_filter;
+use crate::util::glob_match;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    Dir,
+    File,
+    Symlink,
+    Marker,
+}
+
+#[derive(Debug)]
+pub struct Node {
+    pub name: String,
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    /// ファイルのバイト数。メタデータが読めなかった場合は `None`
+    pub size: Option<u64>,
+    /// Unix のパーミッションビット (型ビットは除く)。非 Unix では `None`
+    pub mode: Option<u32>,
+    /// 最終更新時刻。メタデータが読めなかった場合は `None`
+    pub mtime: Option<std::time::SystemTime>,
+    /// `--mount-info` 用のデバイス ID。フラグなし・非 Unix では `None`
+    pub dev: Option<u64>,
+    /// 親と異なるデバイスに属する (= マウントポイント) か
+    pub is_mount: bool,
+    pub note: Option<String>,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    pub fn marker(text: &str) -> Self {
+        Node {
+            name: text.to_string(),
+            path: PathBuf::new(),
+            kind: EntryKind::Marker,
+            size: None,
+            mode: None,
+            mtime: None,
+            dev: None,
+            is_mount: false,
+            note: None,
+            children: Vec::new(),
+        }
+    }
+}
+
+/// `--max-open-dirs` 用のカウンティングセマフォ。同時に開くディレクトリ
+/// ハンドル数をここで一元的に制限する (並列走査が入っても共有できる)
+#[derive(Debug)]
+pub struct DirSemaphore {
+    permits: std::sync::Mutex<usize>,
+    released: std::sync::Condvar,
+}
+
+impl DirSemaphore {
+    pub fn new(max: usize) -> Self {
+        DirSemaphore {
+            permits: std::sync::Mutex::new(max),
+            released: std::sync::Condvar::new(),
+        }
+    }
+
+    /// 空きが出るまで待って許可を取る。許可は drop で返却される
+    pub fn acquire(&self) -> DirPermit<'_> {
+        let mut permits = self.permits.lock().unwrap();
+        while *permits == 0 {
+            permits = self.released.wait(permits).unwrap();
+        }
+        *permits -= 1;
+        DirPermit { semaphore: self }
+    }
+}
+
+pub struct DirPermit<'a> {
+    semaphore: &'a DirSemaphore,
+}
+
+impl Drop for DirPermit<'_> {
+    fn drop(&mut self) {
+        *self.semaphore.permits.lock().unwrap() += 1;
+        self.semaphore.released.notify_one();
+    }
+}
+
+#[derive(Debug, Default)]
+struct WalkState {
+    total_bytes: u64,
+    budget_reached: bool,
+    scanned: usize,
+    errors: Vec<(PathBuf, String)>,
+    /// `--follow-only-dirs` のループ検出用。走査済みディレクトリの正規パス
+    visited_dirs: HashSet<PathBuf>,
+    /// `--max-open-dirs` の上限。未指定なら制限なし
+    open_dirs: Option<DirSemaphore>,
+    /// `--verify-utf8` で見つかった不正な名前のパス
+    invalid_names: Vec<PathBuf>,
+    /// メタデータ取得回数 (`WalkOutcome::stat_calls` に載せる)
+    stat_calls: usize,
+}
+
+/// `--on-error` の方針に従って走査中のエントリ単位のエラーを処理する。
+/// 戻り値はマーカーを出すかどうか。`abort` ではエラーを上へ伝播して
+/// 走査ごと打ち切る
+fn handle_entry_error(
+    config: &Config,
+    state: &mut WalkState,
+    path: &Path,
+    err: AppError,
+) -> Result<bool, AppError> {
+    match config.on_error {
+        OnError::Abort => Err(err),
+        OnError::Skip => Ok(false),
+        OnError::Warn => {
+            eprintln!("warning: {}: {}", path.display(), err);
+            state.errors.push((path.to_path_buf(), err.to_string()));
+            Ok(true)
+        }
+    }
+}
+
+/// サブディレクトリ走査の失敗を方針に従ってノード化する。
+/// `--quiet-permission` では権限エラーをマーカーなしの空ディレクトリ扱いに
+/// する (`--on-error` が制御するのはフローで、こちらは表示)
+fn dir_error_children(
+    config: &Config,
+    state: &mut WalkState,
+    path: &Path,
+    err: AppError,
+) -> Result<Vec<Node>, AppError> {
+    if config.quiet_permission && matches!(err, AppError::PermissionDenied(_)) {
+        return Ok(Vec::new());
+    }
+    let marker = format!("[{}]", err);
+    if handle_entry_error(config, state, path, err)? {
+        Ok(vec![Node::marker(&marker)])
+    } else {
+        Ok(Vec::new())
+    }
+}
+
+/// `--progress-json` のイベントを何エントリごとに出すか
+const PROGRESS_INTERVAL: usize = 100;
+
+/// `--progress-json` の 1 イベントを JSON Lines 形式で整形する
+pub fn progress_event(scanned: usize, depth: usize) -> String {
+    format!("{{\"scanned\":{},\"depth\":{}}}", scanned, depth)
+}
+
+/// 走査結果のツリーと、走査中にスキップしたパスのエラー一覧
+#[derive(Debug)]
+pub struct WalkOutcome {
+    pub root: Node,
+    pub errors: Vec<(PathBuf, String)>,
+    /// 走査中にメタデータを取得した回数。エントリごとに 1 回だけになる
+    /// ことを検証できるよう数えておく
+    pub stat_calls: usize,
+}
+
+pub fn validate_path<P: AsRef<Path>>(path: P) -> Result<(), AppError> {
+    let path_ref = path.as_ref();
+
+    let metadata = fs::metadata(path_ref).map_err(|e| match e.kind() {
+        ErrorKind::NotFound => AppError::PathNotFound(path_ref.to_path_buf()),
+        _ => AppError::Io(e),
+    })?;
+
+    if !metadata.is_dir() {
+        return Err(AppError::NotADirectory(path_ref.to_path_buf()));
+    }
+
+    Ok(())
+}
+
+/// `--no-dereference-root` 用: シンボリックリンクを辿らず、ルート自身の
+/// 型で検証する。ディレクトリを指すリンクでも `NotADirectory` になる
+pub fn validate_path_no_follow<P: AsRef<Path>>(path: P) -> Result<(), AppError> {
+    let path_ref = path.as_ref();
+
+    let metadata = fs::symlink_metadata(path_ref).map_err(|e| match e.kind() {
+        ErrorKind::NotFound => AppError::PathNotFound(path_ref.to_path_buf()),
+        _ => AppError::Io(e),
+    })?;
+
+    if !metadata.is_dir() {
+        return Err(AppError::NotADirectory(path_ref.to_path_buf()));
+    }
+
+    Ok(())
+}
+
+/// 1 エントリ分の `DirEntry` と、一度だけ取得したメタデータの組。
+/// サイズ・時刻・パーミッションなどの消費者はすべてこの値を使い回す
+pub struct EntryInfo {
+    pub entry: fs::DirEntry,
+    pub metadata: Option<fs::Metadata>,
+}
+
+/// ディレクトリを読み、各エントリのメタデータをここで 1 回だけ取得する。
+/// `--fast` では `file_type()` で分類できるエントリの stat を省き、
+/// 種別が分からなかったときだけメタデータ取得に落とす
+pub fn read_directory_info<P: AsRef<Path>>(path: P, fast: bool) -> Result<Vec<EntryInfo>, AppError> {
+    Ok(read_directory(path)?
+        .into_iter()
+        .map(|entry| {
+            let metadata = if fast && entry.file_type().is_ok() {
+                None
+            } else {
+                entry.metadata().ok()
+            };
+            EntryInfo { entry, metadata }
+        })
+        .collect())
+}
+
+pub fn read_directory<P: AsRef<Path>>(path: P) -> Result<Vec<fs::DirEntry>, AppError> {
+    let path_ref = path.as_ref();
+    fs::read_dir(path_ref)
+        .map_err(|e| match e.kind() {
+            ErrorKind::PermissionDenied => AppError::PermissionDenied(path_ref.to_path_buf()),
+            _ => AppError::Io(e),
+        })?
+        .map(|res| {
+            res.map_err(AppError::from)
+        })
+        .collect()
+}
+
+/// エントリの属するデバイス ID。非 Unix では `None`
+fn entry_dev(metadata: &fs::Metadata) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some(metadata.dev())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// `--resolve-mounts` 用: デバイス ID が変わる境界ディレクトリの注釈を
+/// 返す。同一デバイスなら `None`。デバイス名はマウントテーブルから
+/// 引けた場合に使い、引けなければ major:minor で表す
+pub fn mount_note(parent_dev: u64, child_dev: u64, mount_point: &Path) -> Option<String> {
+    if parent_dev == child_dev {
+        return None;
+    }
+    let label = mount_source(mount_point).unwrap_or_else(|| {
+        // Linux の dev_t エンコーディングから major/minor を取り出す
+        let major = (child_dev >> 8) & 0xfff | ((child_dev >> 32) & !0xfff);
+        let minor = child_dev & 0xff | ((child_dev >> 12) & 0xffff_ff00);
+        format!("dev {}:{}", major, minor)
+    });
+    Some(format!("[mount: {}]", label))
+}
+
+fn mount_source(mount_point: &Path) -> Option<String> {
+    let content = fs::read_to_string("/proc/self/mounts").ok()?;
+    content.lines().find_map(|line| {
+        let mut parts = line.split_whitespace();
+        let source = parts.next()?;
+        let target = parts.next()?;
+        (Path::new(target) == mount_point).then(|| source.to_string())
+    })
+}
+
+/// `--time-kind` に応じたタイムスタンプを取り出す。作成時刻が取れない
+/// プラットフォームでは mtime に退避する
+fn entry_time(metadata: &fs::Metadata, kind: TimeKind) -> Option<std::time::SystemTime> {
+    match kind {
+        TimeKind::Modified => metadata.modified().ok(),
+        TimeKind::Accessed => metadata.accessed().ok(),
+        TimeKind::Created => metadata.created().or_else(|_| metadata.modified()).ok(),
+    }
+}
+
+/// Unix のパーミッションビット (下位 12 ビット)。非 Unix では `None`
+fn entry_mode(metadata: &fs::Metadata) -> Option<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        Some(metadata.permissions().mode() & 0o7777)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// `--emit-root-error-as-tree` 用: 失敗したルートをエラーマーカー付きの
+/// 単一ノードとして表す。複数ルートの一括処理で欠落を防ぐ
+pub fn root_error_node(root: &Path, error: &AppError) -> Node {
+    let reason = match error {
+        AppError::PathNotFound(_) => "not found".to_string(),
+        AppError::NotADirectory(_) => "not a directory".to_string(),
+        AppError::PermissionDenied(_) => "permission denied".to_string(),
+        other => other.to_string(),
+    };
+    Node {
+        name: root.display().to_string(),
+        path: root.to_path_buf(),
+        kind: EntryKind::File,
+        size: None,
+        mode: None,
+        mtime: None,
+        dev: None,
+        is_mount: false,
+        note: Some(format!("[error: {}]", reason)),
+        children: Vec::new(),
+    }
+}
+
+pub fn walk(config: &Config) -> Result<WalkOutcome, AppError> {
+    let mut state = WalkState {
+        open_dirs: config.max_open_dirs.map(DirSemaphore::new),
+        ..WalkState::default()
+    };
+    // file:// リンク等で絶対パスが必要になるため、走査は正規化したパスで行う
+    let abs_root = fs::canonicalize(&config.root).unwrap_or_else(|_| config.root.clone());
+    // --allow-file: ディレクトリでないルートは 1 ノードのツリーとして返す
+    if config.allow_file
+        && let Ok(metadata) = fs::metadata(&abs_root)
+        && !metadata.is_dir()
+    {
+        let name = config
+            .root_label
+            .clone()
+            .unwrap_or_else(|| config.root.display().to_string());
+        return Ok(WalkOutcome {
+            root: Node {
+                name,
+                path: abs_root,
+                kind: EntryKind::File,
+                size: Some(metadata.len()),
+                mode: entry_mode(&metadata),
+                mtime: entry_time(&metadata, config.time_kind),
+                dev: None,
+                is_mount: false,
+                note: None,
+                children: Vec::new(),
+            },
+            errors: Vec::new(),
+            stat_calls: 1,
+        });
+    }
+    let children = walk_dir(&abs_root, config, &mut state, 1)?;
+    if config.verify_utf8 && !state.invalid_names.is_empty() {
+        return Err(AppError::InvalidUtf8(state.invalid_names));
+    }
+
+    // --root-label は表示名だけを差し替える (走査は実パスで行う)
+    let mut root_name = config
+        .root_label
+        .clone()
+        .unwrap_or_else(|| config.root.display().to_string());
+    // --show-branch はリポジトリ内でだけブランチ名を添える
+    if config.show_branch
+        && let Some(branch) = crate::repo::current_branch(&abs_root)
+    {
+        root_name = format!("{} ({})", root_name, branch);
+    }
+
+    Ok(WalkOutcome {
+        root: Node {
+            name: root_name,
+            path: abs_root,
+            kind: EntryKind::Dir,
+            size: None,
+            mode: None,
+            mtime: None,
+            dev: None,
+            is_mount: false,
+            note: None,
+            children,
+        },
+        errors: state.errors,
+        stat_calls: state.stat_calls,
+    })
+}
+
+fn walk_dir(
+    path: &Path,
+    config: &Config,
+    state: &mut WalkState,
+    depth: usize,
+) -> Result<Vec<Node>, AppError> {
+    // ルートごとの深さ制限 (--max-depth)。制限より深い階層には入らない
+    if let Some(limit) = config.max_depth
+        && depth > limit
+    {
+        return Ok(Vec::new());
+    }
+
+    let mut entries = {
+        let _permit = state.open_dirs.as_ref().map(DirSemaphore::acquire);
+        read_directory_info(path, config.fast)?
+    };
+    entries.sort_by_key(|info| info.entry.file_name());
+    state.stat_calls += entries.iter().filter(|i| i.metadata.is_some()).count();
+
+    // --resolve-mounts / --mount-info のデバイス境界検出用に、
+    // この階層のデバイス ID を取る
+    let parent_dev = if config.resolve_mounts || config.mount_info {
+        fs::metadata(path).ok().and_then(|m| entry_dev(&m))
+    } else {
+        None
+    };
+
+    let follow_dir_links =
+        config.follow_only_dirs || config.on_symlink == Some(SymlinkPolicy::Follow);
+    if follow_dir_links {
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        state.visited_dirs.insert(canonical);
+    }
+
+    let mut nodes = Vec::new();
+    for info in entries {
+        if state.budget_reached {
+            break;
+        }
+        state.scanned += 1;
+        if config.progress_json && state.scanned.is_multiple_of(PROGRESS_INTERVAL) {
+            eprintln!("{}", progress_event(state.scanned, depth));
+        }
+
+        let entry = info.entry;
+        let entry_path = entry.path();
+        // --fast: file_type() だけで分類し、stat に依存する注釈や
+        // フィルタはすべて省く。種別不明のエントリだけ通常経路に落とす
+        if config.fast
+            && info.metadata.is_none()
+            && let Ok(file_type) = entry.file_type()
+        {
+            if config.verify_utf8 && entry.file_name().to_str().is_none() {
+                state.invalid_names.push(entry_path.clone());
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            let is_symlink = file_type.is_symlink();
+            let is_dir = !is_symlink && file_type.is_dir();
+            if exclusion_reason(config, &entry_path, &name, is_dir, is_symlink, None).is_some() {
+                continue;
+            }
+            if is_dir {
+                let children = match walk_dir(&entry_path, config, state, depth + 1) {
+                    Ok(children) => children,
+                    Err(e) => dir_error_children(config, state, &entry_path, e)?,
+                };
+                nodes.push(Node {
+                    name,
+                    path: entry_path,
+                    kind: EntryKind::Dir,
+                    size: None,
+                    mode: None,
+                    mtime: None,
+                    dev: None,
+                    is_mount: false,
+                    note: None,
+                    children,
+                });
+            } else {
+                nodes.push(Node {
+                    name,
+                    path: entry_path,
+                    kind: if is_symlink { EntryKind::Symlink } else { EntryKind::File },
+                    size: None,
+                    mode: None,
+                    mtime: None,
+                    dev: None,
+                    is_mount: false,
+                    note: None,
+                    children: Vec::new(),
+                });
+            }
+            continue;
+        }
+        // シンボリックリンクはリンク自身として扱う (デリファレンスしない)
+        let is_symlink = entry
+            .file_type()
+            .map(|t| t.is_symlink())
+            .unwrap_or(false);
+        let metadata = match info.metadata {
+            Some(m) => m,
+            None => {
+                let e = std::io::Error::other("metadata unavailable");
+                handle_entry_error(config, state, &entry_path, AppError::Io(e))?;
+                continue;
+            }
+        };
+        // --verify-utf8: 不正な名前は損失変換せずエラーとして集める
+        if config.verify_utf8 && entry.file_name().to_str().is_none() {
+            state.invalid_names.push(entry_path.clone());
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        let is_dir = !is_symlink && metadata.is_dir();
+        if let Some(reason) =
+            exclusion_reason(config, &entry_path, &name, is_dir, is_symlink, Some(&metadata))
+        {
+            // --dry-run-filters では除外せず、理由を注釈して表示する
+            if config.dry_run_filters {
+                let kind = if is_symlink {
+                    EntryKind::Symlink
+                } else if is_dir {
+                    EntryKind::Dir
+                } else {
+                    EntryKind::File
+                };
+                nodes.push(Node {
+                    name,
+                    path: entry_path,
+                    kind,
+                    size: Some(metadata.len()),
+                    mode: entry_mode(&metadata),
+                    mtime: entry_time(&metadata, config.time_kind),
+                    dev: None,
+                    is_mount: false,
+                    note: Some(format!("[excluded: {}]", reason)),
+                    children: Vec::new(),
+                });
+            }
+            continue;
+        }
+
+        if is_symlink {
+            // --on-symlink: 統一ポリシー。skip は何も出さず、show は辿らずに
+            // リンク先を注釈する。follow は下のディレクトリ降下パスを共有する
+            match config.on_symlink {
+                Some(SymlinkPolicy::Skip) => continue,
+                Some(SymlinkPolicy::Show) => {
+                    let note = symlink_target_note(config, &entry_path);
+                    nodes.push(Node {
+                        name,
+                        path: entry_path,
+                        kind: EntryKind::Symlink,
+                        size: Some(metadata.len()),
+                        mode: entry_mode(&metadata),
+                        mtime: entry_time(&metadata, config.time_kind),
+                        dev: None,
+                        is_mount: false,
+                        note,
+                        children: Vec::new(),
+                    });
+                    continue;
+                }
+                Some(SymlinkPolicy::Follow) | None => {}
+            }
+            // --follow-only-dirs: ディレクトリを指すリンクだけ辿る。
+            // ファイルへのリンクは `-> target` 表示のままデリファレンスしない
+            if follow_dir_links
+                && let Ok(target) = fs::metadata(&entry_path)
+                && target.is_dir()
+            {
+                let canonical =
+                    fs::canonicalize(&entry_path).unwrap_or_else(|_| entry_path.clone());
+                if state.visited_dirs.contains(&canonical) {
+                    // --silent-loops ではマーカーを出さず降下だけやめる。
+                    // 既定 (--report-loops) ではエラー要約にも載せる
+                    let note = if config.silent_loops {
+                        None
+                    } else {
+                        state
+                            .errors
+                            .push((entry_path.clone(), "symlink loop detected".to_string()));
+                        Some("[cycle]".to_string())
+                    };
+                    nodes.push(Node {
+                        name,
+                        path: entry_path,
+                        kind: EntryKind::Symlink,
+                        size: None,
+                        mode: None,
+                        mtime: None,
+                        dev: None,
+                        is_mount: false,
+                        note,
+                        children: Vec::new(),
+                    });
+                    continue;
+                }
+                let children = match walk_dir(&entry_path, config, state, depth + 1) {
+                    Ok(children) => children,
+                    Err(e) => dir_error_children(config, state, &entry_path, e)?,
+                };
+                nodes.push(Node {
+                    name,
+                    path: entry_path,
+                    kind: EntryKind::Dir,
+                    size: None,
+                    mode: entry_mode(&target),
+                    mtime: entry_time(&target, config.time_kind),
+                    dev: None,
+                    is_mount: false,
+                    note: None,
+                    children,
+                });
+                continue;
+            }
+            // --on-symlink=follow: ファイルを指すリンクはリンク先の
+            // メタデータでファイルとして出す。切れたリンクだけ注釈して残す
+            if config.on_symlink == Some(SymlinkPolicy::Follow) {
+                match fs::metadata(&entry_path) {
+                    Ok(target) => nodes.push(Node {
+                        name,
+                        path: entry_path,
+                        kind: EntryKind::File,
+                        size: Some(target.len()),
+                        mode: entry_mode(&target),
+                        mtime: entry_time(&target, config.time_kind),
+                        dev: None,
+                        is_mount: false,
+                        note: None,
+                        children: Vec::new(),
+                    }),
+                    Err(_) => nodes.push(Node {
+                        name,
+                        path: entry_path,
+                        kind: EntryKind::Symlink,
+                        size: Some(metadata.len()),
+                        mode: entry_mode(&metadata),
+                        mtime: entry_time(&metadata, config.time_kind),
+                        dev: None,
+                        is_mount: false,
+                        note: Some("[broken link]".to_string()),
+                        children: Vec::new(),
+                    }),
+                }
+                continue;
+            }
+            let mut note = if config.follow_only_dirs {
+                symlink_target_note(config, &entry_path)
+            } else {
+                None
+            };
+            // --include-target-metadata: 注釈にはリンク先のメタデータを使う。
+            // 切れたリンクはリンク自身のメタデータに戻してマーカーを付ける
+            let meta = if config.include_target_metadata {
+                match fs::metadata(&entry_path) {
+                    Ok(target) => target,
+                    Err(_) => {
+                        let broken = "[broken link]".to_string();
+                        note = Some(match note {
+                            Some(n) => format!("{} {}", n, broken),
+                            None => broken,
+                        });
+                        metadata
+                    }
+                }
+            } else {
+                metadata
+            };
+            nodes.push(Node {
+                name,
+                path: entry_path,
+                kind: EntryKind::Symlink,
+                size: Some(meta.len()),
+                mode: entry_mode(&meta),
+                mtime: entry_time(&meta, config.time_kind),
+                dev: None,
+                is_mount: false,
+                note,
+                children: Vec::new(),
+            });
+            continue;
+        }
+
+        let mut note = config.status_note(&entry_path);
+        // --xattr: 拡張属性名を注釈として添える (属性がなければ何も出ない)
+        #[cfg(unix)]
+        if config.xattr
+            && let Some(attrs) = xattr_note(&entry_path)
+        {
+            note = Some(match note {
+                Some(n) => format!("{} {}", n, attrs),
+                None => attrs,
+            });
+        }
+        // --mount-info: JSON 出力向けにデバイス ID と境界判定を残す
+        let (dev, is_mount) = if config.mount_info {
+            let dev = entry_dev(&metadata);
+            (dev, parent_dev.is_some() && dev.is_some() && dev != parent_dev)
+        } else {
+            (None, false)
+        };
+        if metadata.is_dir()
+            && let Some(parent_dev) = parent_dev
+            && let Some(child_dev) = entry_dev(&metadata)
+            && let Some(mount) = mount_note(parent_dev, child_dev, &entry_path)
+        {
+            note = Some(match note {
+                Some(n) => format!("{} {}", n, mount),
+                None => mount,
+            });
+        }
+
+        if metadata.is_dir() {
+            // collapse 対象のディレクトリは表示するが中へは降りない
+            if config.collapse_dirs.iter().any(|p| glob_match(p, &name)) {
+                nodes.push(Node {
+                    name,
+                    path: entry_path,
+                    kind: EntryKind::Dir,
+                    size: None,
+                    mode: entry_mode(&metadata),
+                    mtime: entry_time(&metadata, config.time_kind),
+                    dev: None,
+                    is_mount: false,
+                    note: Some("[collapsed]".to_string()),
+                    children: Vec::new(),
+                });
+                continue;
+            }
+            // 読めないサブディレクトリは走査を止めず、マーカーで示して記録する
+            let children = match walk_dir(&entry_path, config, state, depth + 1) {
+                Ok(children) => children,
+                Err(e) => dir_error_children(config, state, &entry_path, e)?,
+            };
+            nodes.push(Node {
+                name,
+                path: entry_path,
+                kind: EntryKind::Dir,
+                size: None,
+                mode: entry_mode(&metadata),
+                mtime: entry_time(&metadata, config.time_kind),
+                dev,
+                is_mount,
+                note,
+                children,
+            });
+        } else {
+            state.total_bytes += metadata.len();
+            nodes.push(Node {
+                name,
+                path: entry_path,
+                kind: EntryKind::File,
+                size: Some(metadata.len()),
+                mode: entry_mode(&metadata),
+                mtime: entry_time(&metadata, config.time_kind),
+                dev,
+                is_mount,
+                note,
+                children: Vec::new(),
+            });
+
+            if let Some(budget) = config.max_total_size
+                && state.total_bytes > budget
+            {
+                state.budget_reached = true;
+                nodes.push(Node::marker("[size budget reached]"));
+            }
+        }
+    }
+
+    Ok(nodes)
+}
+
+/// `--max-depth-auto` が出力全体で収めようとするエントリ数の目安
+pub const AUTO_DEPTH_BUDGET: usize = 1000;
+
+/// `--max-depth-auto` 用: 各階層のエントリ数を幅優先で安く数え、累計が
+/// budget を超えない最も深いレベルを返す (最低 1)
+pub fn auto_max_depth(root: &Path, budget: usize) -> usize {
+    let mut level = vec![root.to_path_buf()];
+    let mut depth = 0;
+    let mut cumulative = 0;
+    while !level.is_empty() {
+        let mut count = 0;
+        let mut next = Vec::new();
+        for dir in &level {
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                count += 1;
+                if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                    next.push(entry.path());
+                }
+            }
+        }
+        if cumulative + count > budget {
+            break;
+        }
+        cumulative += count;
+        depth += 1;
+        level = next;
+    }
+    depth.max(1)
+}
+
+/// `--merge-roots` 用: 複数ルートのツリーを `<roots>` という仮想親の
+/// 子としてまとめ、1 本のツリーとして描画できるようにする
+pub fn merge_roots(trees: Vec<Node>) -> Node {
+    Node {
+        name: "<roots>".to_string(),
+        path: PathBuf::new(),
+        kind: EntryKind::Dir,
+        size: None,
+        mode: None,
+        mtime: None,
+        dev: None,
+        is_mount: false,
+        note: None,
+        children: trees,
+    }
+}
+
+/// `--exclude-larger-subtree` 用: 子孫総数が N を超えるディレクトリを
+/// 中身ごと畳む。直接の子だけを数える filelimit 系と違い部分木全体を見る
+pub fn collapse_large_subtrees(node: &mut Node, max: usize) {
+    for child in &mut node.children {
+        if child.kind != EntryKind::Dir {
+            continue;
+        }
+        if descendant_count(child) > max {
+            child.children.clear();
+            child.note = Some(format!("[{}+ descendants, collapsed]", max));
+        } else {
+            collapse_large_subtrees(child, max);
+        }
+    }
+}
+
+/// `--max-siblings` 用: 各ディレクトリの子を先頭 N 件に切り詰め、
+/// 残数を `... and K more` マーカーで示す。ソート後に適用する
+pub fn truncate_siblings(node: &mut Node, max: usize) {
+    if node.children.len() > max {
+        let hidden = node.children.len() - max;
+        node.children.truncate(max);
+        node.children
+            .push(Node::marker(&format!("... and {} more", hidden)));
+    }
+    for child in &mut node.children {
+        truncate_siblings(child, max);
+    }
+}
+
+/// エントリがどのフィルタで除外されるかを返す。`None` なら表示対象。
+/// フィルタ式と時刻フィルタはファイルにのみ適用し、ディレクトリと
+/// シンボリックリンクは構造のため残す
+fn exclusion_reason(
+    config: &Config,
+    path: &Path,
+    name: &str,
+    is_dir: bool,
+    is_symlink: bool,
+    // --fast では stat を省くため、メタデータ依存のフィルタは判定できない
+    metadata: Option<&fs::Metadata>,
+) -> Option<String> {
+    if !config.all && name.starts_with('.') {
+        return Some("hidden".to_string());
+    }
+    if let Some(pattern) = config.ignored_by(path, name, is_dir) {
+        return Some(format!("ignore pattern '{}'", pattern));
+    }
+    // --skip-names: グロブではなく名前の完全一致で除外する
+    if config.skip_names.contains(name) {
+        return Some("skip-names list".to_string());
+    }
+    if !is_dir && !is_symlink {
+        // -P: いずれかのパターンに一致しないファイルは表示しない
+        if !config.match_patterns.is_empty()
+            && !config.match_patterns.iter().any(|p| glob_match(p, name))
+        {
+            return Some("no matching -P pattern".to_string());
+        }
+        if let Some(metadata) = metadata {
+            if let Some(filter) = &config.filter
+                && !eval_filter(filter, name, metadata)
+            {
+                return Some("filter expression".to_string());
+            }
+            if !passes_time_filters(config, metadata) {
+                return Some("time filter".to_string());
+            }
+        }
+    }
+    None
+}
+
+/// シンボリックリンクの `-> target` 注釈を作る。`--relative-targets` では
+/// ルート内を指すリンク先をリンクのあるディレクトリからの相対で表示し、
+/// ルート外のものは絶対パスのまま残す
+fn symlink_target_note(config: &Config, entry_path: &Path) -> Option<String> {
+    let target = fs::read_link(entry_path).ok()?;
+    if !config.relative_targets {
+        return Some(format!("-> {}", target.display()));
+    }
+    let parent = entry_path.parent().unwrap_or(Path::new(""));
+    let absolute = if target.is_absolute() {
+        target.clone()
+    } else {
+        parent.join(&target)
+    };
+    let root = fs::canonicalize(&config.root).unwrap_or_else(|_| config.root.clone());
+    let resolved = fs::canonicalize(&absolute).unwrap_or(absolute.clone());
+    if resolved.starts_with(&root) {
+        let base = fs::canonicalize(parent).unwrap_or_else(|_| parent.to_path_buf());
+        return Some(format!(
+            "-> {}",
+            crate::util::relative_to(&resolved, &base).display()
+        ));
+    }
+    Some(format!("-> {}", absolute.display()))
+}
+
+/// `--xattr` 用: エントリの拡張属性名を注釈形式で返す。属性がなければ `None`
+#[cfg(unix)]
+fn xattr_note(path: &Path) -> Option<String> {
+    let names: Vec<String> = xattr::list(path)
+        .ok()?
+        .filter_map(|name| name.to_str().map(str::to_string))
+        .collect();
+    if names.is_empty() {
+        None
+    } else {
+        Some(format!("[xattr: {}]", names.join(", ")))
+    }
+}
+
+/// 基準ファイルの mtime との比較フィルタ (`--newer-than`/`--older-than`)
+fn passes_time_filters(config: &Config, metadata: &fs::Metadata) -> bool {
+    if config.newer_than_time.is_none() && config.older_than_time.is_none() {
+        return true;
+    }
+    let Ok(mtime) = metadata.modified() else {
+        return false;
+    };
+    if let Some(reference) = config.newer_than_time
+        && mtime <= reference
+    {
+        return false;
+    }
+    if let Some(reference) = config.older_than_time
+        && mtime >= reference
+    {
+        return false;
+    }
+    true
+}
+
+/// `--type` 指定のエントリ種別だけを残す。種別に `d` がなくても、表示対象の
+/// 子孫を持つディレクトリは構造のために残す
+pub fn prune_types(node: &mut Node, types: &[EntryKind]) {
+    retain_types(&mut node.children, types);
+}
+
+fn retain_types(children: &mut Vec<Node>, types: &[EntryKind]) -> bool {
+    let mut any_kept = false;
+    children.retain_mut(|child| {
+        let keep = match child.kind {
+            EntryKind::Marker => true,
+            EntryKind::Dir => {
+                let has_visible = retain_types(&mut child.children, types);
+                types.contains(&EntryKind::Dir) || has_visible
+            }
+            kind => types.contains(&kind),
+        };
+        any_kept |= keep && child.kind != EntryKind::Marker;
+        keep
+    });
+    any_kept
+}
+
+/// `--error-summary` 用: スキップしたパスの一覧を整形する
+/// `--collapse-files` 用: 各ディレクトリのファイルを `N files` の 1 行に
+/// まとめる。サブディレクトリは従来どおり展開する
+pub fn collapse_files(node: &mut Node) {
+    let files = node
+        .children
+        .iter()
+        .filter(|c| matches!(c.kind, EntryKind::File | EntryKind::Symlink))
+        .count();
+    node.children
+        .retain(|c| !matches!(c.kind, EntryKind::File | EntryKind::Symlink));
+    if files > 0 {
+        let label = if files == 1 { "file" } else { "files" };
+        node.children
+            .push(Node::marker(&format!("{} {}", files, label)));
+    }
+    for child in &mut node.children {
+        if child.kind == EntryKind::Dir {
+            collapse_files(child);
+        }
+    }
+}
+
+/// `--file-max-depth` 用: 指定より深い階層のファイルを取り除く。
+/// ディレクトリ構造はそのまま深く展開される (ルート直下が深さ 1)
+pub fn prune_files_below(node: &mut Node, max: usize, depth: usize) {
+    if depth >= max {
+        node.children
+            .retain(|c| !matches!(c.kind, EntryKind::File | EntryKind::Symlink));
+    }
+    for child in &mut node.children {
+        if child.kind == EntryKind::Dir {
+            prune_files_below(child, max, depth + 1);
+        }
+    }
+}
+
+/// `--fold-extensions` 用: 1 つのディレクトリで同じ拡張子のファイルが
+/// しきい値を超えたら `*.ext (N files)` の 1 行にまとめる。拡張子のない
+/// ファイルとしきい値以下のグループはそのまま残す
+pub fn fold_extensions(node: &mut Node, threshold: usize) {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for child in &node.children {
+        if child.kind == EntryKind::File
+            && let Some(ext) = Path::new(&child.name).extension()
+        {
+            *counts.entry(ext.to_string_lossy().to_string()).or_default() += 1;
+        }
+    }
+    let mut folded: Vec<String> = counts
+        .into_iter()
+        .filter(|(_, n)| *n > threshold)
+        .map(|(ext, _)| ext)
+        .collect();
+    folded.sort();
+    for ext in folded {
+        let count = node
+            .children
+            .iter()
+            .filter(|c| {
+                c.kind == EntryKind::File
+                    && Path::new(&c.name).extension().is_some_and(|e| *e == *ext.as_str())
+            })
+            .count();
+        node.children.retain(|c| {
+            c.kind != EntryKind::File
+                || Path::new(&c.name).extension().is_none_or(|e| *e != *ext.as_str())
+        });
+        node.children
+            .push(Node::marker(&format!("*.{} ({} files)", ext, count)));
+    }
+    for child in &mut node.children {
+        if child.kind == EntryKind::Dir {
+            fold_extensions(child, threshold);
+        }
+    }
+}
+
+/// `--deduplicate-output` 用: 構造が同一のサブツリーを 2 回目以降は
+/// `[identical to <初出パス>]` の注釈に置き換える。構造ハッシュは
+/// 子孫の名前と種類からボトムアップで計算する
+pub fn deduplicate_subtrees(root: &mut Node) {
+    use std::hash::{Hash, Hasher};
+
+    fn structural_hash(node: &Node) -> u64 {
+        fn feed(node: &Node, hasher: &mut std::collections::hash_map::DefaultHasher) {
+            node.children.len().hash(hasher);
+            for child in &node.children {
+                child.name.hash(hasher);
+                (child.kind == EntryKind::Dir).hash(hasher);
+                feed(child, hasher);
+            }
+        }
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        feed(node, &mut hasher);
+        hasher.finish()
+    }
+
+    fn visit(node: &mut Node, seen: &mut HashMap<u64, PathBuf>) {
+        for child in &mut node.children {
+            if child.kind == EntryKind::Dir && !child.children.is_empty() {
+                match seen.entry(structural_hash(child)) {
+                    std::collections::hash_map::Entry::Occupied(first) => {
+                        child.children.clear();
+                        child.note = Some(format!("[identical to {}]", first.get().display()));
+                        continue;
+                    }
+                    std::collections::hash_map::Entry::Vacant(slot) => {
+                        slot.insert(child.path.clone());
+                    }
+                }
+            }
+            visit(child, seen);
+        }
+    }
+
+    let mut seen = HashMap::new();
+    visit(root, &mut seen);
+}
+
+/// `--exec` 用: ツリーの各エントリ (マーカー以外) に対してコマンドを 1 回ずつ
+/// 実行する。パスは `{}` の位置に渡す
+pub fn exec_per_entry(node: &Node, cmd: &[String]) {
+    if node.kind != EntryKind::Marker {
+        run_exec_command(cmd, std::slice::from_ref(&node.path));
+    }
+    for child in &node.children {
+        exec_per_entry(child, cmd);
+    }
+}
+
+/// `--exec ... +` 用: エントリのパスを集めて 1 回 (引数長の上限を超える場合は
+/// 分割して数回) の起動にまとめて渡す
+pub fn exec_batched(node: &Node, cmd: &[String]) {
+    // ARG_MAX に余裕を持たせた上限。超えたら分割起動する
+    const BATCH_BYTES: usize = 128 * 1024;
+    let mut paths = Vec::new();
+    collect_exec_paths(node, &mut paths);
+    let mut batch: Vec<PathBuf> = Vec::new();
+    let mut batch_bytes = 0;
+    for path in paths {
+        let len = path.as_os_str().len() + 1;
+        if !batch.is_empty() && batch_bytes + len > BATCH_BYTES {
+            run_exec_command(cmd, &batch);
+            batch.clear();
+            batch_bytes = 0;
+        }
+        batch_bytes += len;
+        batch.push(path);
+    }
+    if !batch.is_empty() {
+        run_exec_command(cmd, &batch);
+    }
+}
+
+fn collect_exec_paths(node: &Node, paths: &mut Vec<PathBuf>) {
+    if node.kind != EntryKind::Marker {
+        paths.push(node.path.clone());
+    }
+    for child in &node.children {
+        collect_exec_paths(child, paths);
+    }
+}
+
+/// `{}` をパスで置き換えてコマンドを起動する。`{}` がなければ末尾に付ける。
+/// 失敗は警告に留め、走査自体は成功として扱う
+fn run_exec_command(cmd: &[String], paths: &[PathBuf]) {
+    let mut command = std::process::Command::new(&cmd[0]);
+    let mut substituted = false;
+    for arg in &cmd[1..] {
+        if arg == "{}" {
+            command.args(paths);
+            substituted = true;
+        } else {
+            command.arg(arg);
+        }
+    }
+    if !substituted {
+        command.args(paths);
+    }
+    if let Err(e) = command.status() {
+        eprintln!("warning: --exec '{}' failed: {}", cmd[0], e);
+    }
+}
+
+pub fn format_error_summary(errors: &[(PathBuf, String)]) -> String {
+    let mut out = format!("Skipped {} paths due to errors:\n", errors.len());
+    for (path, reason) in errors {
+        out.push_str(&format!("  {}: {}\n", path.display(), reason));
+    }
+    out
+}
+
+/// ノードの子孫エントリの総数 (マーカーは数えない)
+pub fn descendant_count(node: &Node) -> usize {
+    node.children
+        .iter()
+        .filter(|c| c.kind != EntryKind::Marker)
+        .map(|c| 1 + descendant_count(c))
+        .sum()
+}
+
+/// `--fail-on-empty` 用: ツリーに残ったファイル・シンボリックリンクの数。
+/// ディレクトリは経路として常に残るため数えない
+pub fn file_count(node: &Node) -> usize {
+    let own = usize::from(matches!(node.kind, EntryKind::File | EntryKind::Symlink));
+    own + node.children.iter().map(file_count).sum::<usize>()
+}
+
+/// `--min-depth` より浅いエントリを落とす。ただし深いエントリへの経路となる
+/// ディレクトリは構造の文脈として残す
+pub fn prune_min_depth(node: &mut Node, min: usize) {
+    retain_min_depth(&mut node.children, 1, min);
+}
+
+fn retain_min_depth(children: &mut Vec<Node>, depth: usize, min: usize) -> bool {
+    let mut any_kept = false;
+    children.retain_mut(|child| {
+        let keep = match child.kind {
+            EntryKind::Dir => {
+                let has_deep = retain_min_depth(&mut child.children, depth + 1, min);
+                depth >= min || has_deep
+            }
+            _ => depth >= min,
+        };
+        any_kept |= keep;
+        keep
+    });
+    any_kept
+}
+
+/// `--min-depth-flat` 用: 指定した深さ以上のエントリを相対パスで列挙する
+pub fn collect_at_min_depth(node: &Node, min: usize) -> Vec<String> {
+    fn visit(node: &Node, depth: usize, min: usize, prefix: &str, out: &mut Vec<String>) {
+        for child in &node.children {
+            if child.kind == EntryKind::Marker {
+                continue;
+            }
+            let path = if prefix.is_empty() {
+                child.name.clone()
+            } else {
+                format!("{}/{}", prefix, child.name)
+            };
+            if depth >= min {
+                out.push(path.clone());
+            }
+            visit(child, depth + 1, min, &path, out);
+        }
+    }
+
+    let mut out = Vec::new();
+    visit(node, 1, min, "", &mut out);
+    out
+}
+
+/// `--no-indent` 用: ツリーをルートからの相対パス一覧に平坦化する
+/// (走査順、マーカーは除く)
+pub fn flatten_tree(node: &Node) -> Vec<(String, &Node)> {
+    fn visit<'a>(node: &'a Node, prefix: &str, out: &mut Vec<(String, &'a Node)>) {
+        for child in &node.children {
+            if child.kind == EntryKind::Marker {
+                continue;
+            }
+            let path = if prefix.is_empty() {
+                child.name.clone()
+            } else {
+                format!("{}/{}", prefix, child.name)
+            };
+            out.push((path.clone(), child));
+            visit(child, &path, out);
+        }
+    }
+
+    let mut out = Vec::new();
+    visit(node, "", &mut out);
+    out
+}
+
+/// テストでツリーを組み立てるための小さなヘルパ
+#[cfg(test)]
+pub mod test_util {
+    use super::*;
+
+    pub fn file_node(name: &str) -> Node {
+        sized_file_node(name, 0)
+    }
+
+    /// メタデータが読めなかったファイルに相当するノード (size = None)
+    pub fn null_size_file_node(name: &str) -> Node {
+        let mut node = sized_file_node(name, 0);
+        node.size = None;
+        node
+    }
+
+    pub fn sized_file_node(name: &str, size: u64) -> Node {
+        Node {
+            name: name.to_string(),
+            path: PathBuf::from(name),
+            kind: EntryKind::File,
+            size: Some(size),
+            mode: None,
+            mtime: None,
+            dev: None,
+            is_mount: false,
+            note: None,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn dir_node(name: &str, children: Vec<Node>) -> Node {
+        Node {
+            name: name.to_string(),
+            path: PathBuf::from(name),
+            kind: EntryKind::Dir,
+            size: None,
+            mode: None,
+            mtime: None,
+            dev: None,
+            is_mount: false,
+            note: None,
+            children,
+        }
+    }
+
+    pub fn child_names(node: &Node) -> Vec<String> {
+        node.children.iter().map(|c| c.name.clone()).collect()
+    }
+
+    pub fn write_file(path: &Path, bytes: usize) {
+        std::fs::write(path, vec![0u8; bytes]).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::test_util::*;
+    use super::*;
+    use crate::filter::parse_filter;
+    use std::fs::File;
+    use tempfile::NamedTempFile;
+    use tempfile::tempdir;
+
+    #[test]
+    fn root_error_node_missing_root_becomes_error_marker() {
+        let root = PathBuf::from("/no/such/root");
+        let error = validate_path(&root).unwrap_err();
+
+        let node = root_error_node(&root, &error);
+        assert_eq!(node.name, "/no/such/root");
+        assert_eq!(node.note.as_deref(), Some("[error: not found]"));
+        assert!(node.children.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn validate_path_no_follow_rejects_symlink_to_directory() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+        fs::create_dir(path.join("real")).unwrap();
+        std::os::unix::fs::symlink(path.join("real"), path.join("link")).unwrap();
+
+        assert!(validate_path(path.join("link")).is_ok());
+        assert!(matches!(
+            validate_path_no_follow(path.join("link")),
+            Err(AppError::NotADirectory(_))
+        ));
+    }
+
+    #[test]
+    fn validate_path_existing_directory_returns_ok() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path();
+
+        let result = validate_path(path);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn validate_path_nonexistent_path_returns_err() {
+        let temp_dir = tempdir().unwrap();
+        let non_existent_path = temp_dir.path().join("foo");
+
+        let result = validate_path(non_existent_path);
+        assert!(matches!(result, Err(AppError::PathNotFound(_))));
+    }
+
+    #[test]
+    fn validate_path_file_returns_err() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_path_buf();
+
+        let result = validate_path(path);
+        assert!(matches!(result, Err(AppError::NotADirectory(_))));
+    }
+
+    // TODO: テストを動かす方法があれば作成する
+    #[test]
+    #[ignore]
+    fn validate_path_permission_denied_returns_err() {}
+
+    #[test]
+    fn read_directory_empty_directory_returns_ok() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        let entries = read_directory(path).unwrap();
+        assert!(entries.is_empty());
+    }
+
+    #[test]
+    fn read_directory_with_file_returns_ok() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        // ファイルを作成
+        File::create(path.join("file1.txt")).unwrap();
+        File::create(path.join("file2.txt")).unwrap();
+
+        let entries = read_directory(path).unwrap();
+        let names: Vec<_> = entries
+            .iter()
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .collect();
+
+        assert!(names.contains(&"file1.txt".to_string()));
+        assert!(names.contains(&"file2.txt".to_string()));
+        assert_eq!(names.len(), 2);
+    }
+
+    #[test]
+    fn read_directory_with_subdirectories_returns_ok() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        fs::create_dir(path.join("sub1")).unwrap();
+        fs::create_dir(path.join("sub2")).unwrap();
+
+        let entries = read_directory(path).unwrap();
+        let names: Vec<_> = entries
+            .iter()
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .collect();
+
+        assert!(names.contains(&"sub1".to_string()));
+        assert!(names.contains(&"sub2".to_string()));
+        assert_eq!(names.len(), 2);
+    }
+
+    #[test]
+    fn walk_builds_nested_tree() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        fs::create_dir(path.join("sub")).unwrap();
+        File::create(path.join("sub").join("inner.txt")).unwrap();
+        File::create(path.join("a.txt")).unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+
+        assert_eq!(child_names(&tree), vec!["a.txt", "sub"]);
+        assert_eq!(child_names(&tree.children[1]), vec!["inner.txt"]);
+    }
+
+    #[test]
+    fn walk_within_budget_keeps_all_entries() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        write_file(&path.join("a.txt"), 100);
+        write_file(&path.join("b.txt"), 100);
+
+        let config = Config {
+            root: path.to_path_buf(),
+            max_total_size: Some(1024),
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+
+        assert_eq!(child_names(&tree), vec!["a.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn walk_over_budget_stops_with_marker() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        write_file(&path.join("a.txt"), 600);
+        write_file(&path.join("b.txt"), 600);
+        write_file(&path.join("c.txt"), 600);
+
+        let config = Config {
+            root: path.to_path_buf(),
+            max_total_size: Some(1024),
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+
+        let names = child_names(&tree);
+        assert_eq!(names, vec!["a.txt", "b.txt", "[size budget reached]"]);
+    }
+
+    #[test]
+    fn filter_keeps_only_matching_files() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        write_file(&path.join("big.rs"), 200);
+        write_file(&path.join("small.rs"), 10);
+        write_file(&path.join("big.txt"), 200);
+        fs::create_dir(path.join("sub")).unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            filter: Some(parse_filter("ext==rs && size>100").unwrap()),
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+
+        assert_eq!(child_names(&tree), vec!["big.rs", "sub"]);
+    }
+
+    #[test]
+    fn collapse_dir_skips_descending_but_shows_directory() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        fs::create_dir(path.join("node_modules")).unwrap();
+        File::create(path.join("node_modules/pkg.json")).unwrap();
+        fs::create_dir(path.join("src")).unwrap();
+        File::create(path.join("src/main.rs")).unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            collapse_dirs: vec!["node_modules".to_string()],
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+
+        let collapsed = &tree.children[0];
+        assert_eq!(collapsed.name, "node_modules");
+        assert_eq!(collapsed.note.as_deref(), Some("[collapsed]"));
+        assert!(collapsed.children.is_empty());
+        assert_eq!(child_names(&tree.children[1]), vec!["main.rs"]);
+    }
+
+    #[test]
+    fn merge_roots_wraps_trees_under_synthetic_parent() {
+        let first = dir_node("a", vec![file_node("x.txt")]);
+        let second = dir_node("b", vec![]);
+
+        let merged = merge_roots(vec![first, second]);
+
+        assert_eq!(merged.name, "<roots>");
+        assert_eq!(merged.kind, EntryKind::Dir);
+        assert_eq!(child_names(&merged), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn collapse_large_subtrees_folds_busy_dirs_only() {
+        let big_children = (0..20).map(|i| file_node(&format!("f{}.txt", i))).collect();
+        let mut tree = dir_node(
+            ".",
+            vec![
+                dir_node("big", big_children),
+                dir_node("small", vec![file_node("a.txt")]),
+            ],
+        );
+
+        collapse_large_subtrees(&mut tree, 10);
+
+        let big = &tree.children[0];
+        assert!(big.children.is_empty());
+        assert_eq!(big.note.as_deref(), Some("[10+ descendants, collapsed]"));
+        let small = &tree.children[1];
+        assert_eq!(child_names(small), vec!["a.txt"]);
+        assert_eq!(small.note, None);
+    }
+
+    #[test]
+    fn truncate_siblings_keeps_sample_and_reports_rest() {
+        let mut tree = dir_node(
+            ".",
+            (0..10).map(|i| file_node(&format!("f{:02}.txt", i))).collect(),
+        );
+
+        truncate_siblings(&mut tree, 3);
+
+        let names = child_names(&tree);
+        assert_eq!(
+            names,
+            vec!["f00.txt", "f01.txt", "f02.txt", "... and 7 more"]
+        );
+        assert_eq!(tree.children[3].kind, EntryKind::Marker);
+    }
+
+    #[test]
+    fn ignore_vcs_excludes_vcs_dirs_but_not_other_dotdirs() {
+        use crate::config::parse_args;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+        fs::create_dir(path.join(".git")).unwrap();
+        fs::create_dir(path.join(".svn")).unwrap();
+        fs::create_dir(path.join(".config")).unwrap();
+        File::create(path.join("a.txt")).unwrap();
+
+        let args: Vec<String> = ["treer", "--ignore-vcs", "--all", &path.to_string_lossy()]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let config = parse_args(&args).unwrap();
+        let tree = walk(&config).unwrap().root;
+
+        assert_eq!(child_names(&tree), vec![".config", "a.txt"]);
+    }
+
+    #[test]
+    fn time_kind_accessed_reports_atime() {
+        use crate::config::TimeKind;
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+        let file = File::create(path.join("a.txt")).unwrap();
+        let atime = UNIX_EPOCH + Duration::from_secs(1_000);
+        let mtime = UNIX_EPOCH + Duration::from_secs(2_000);
+        file.set_times(
+            fs::FileTimes::new().set_accessed(atime).set_modified(mtime),
+        )
+        .unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            time_kind: TimeKind::Accessed,
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+        assert_eq!(tree.children[0].mtime, Some(atime));
+
+        let config = Config {
+            root: path.to_path_buf(),
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+        assert_eq!(tree.children[0].mtime, Some(mtime));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mount_note_flags_device_changes_only() {
+        let boundary = PathBuf::from("/no/such/mountpoint");
+
+        assert_eq!(mount_note(7, 7, &boundary), None);
+        let note = mount_note(7, 8, &boundary).unwrap();
+        assert!(note.starts_with("[mount: "));
+        assert!(note.ends_with(']'));
+    }
+
+    #[test]
+    fn max_depth_limits_traversal() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+        fs::create_dir_all(path.join("a/b")).unwrap();
+        File::create(path.join("a/top.txt")).unwrap();
+        File::create(path.join("a/b/deep.txt")).unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            max_depth: Some(2),
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+
+        let a = &tree.children[0];
+        assert_eq!(child_names(a), vec!["b", "top.txt"]);
+        assert!(a.children[0].children.is_empty());
+    }
+
+    #[test]
+    fn walk_hides_dotfiles_unless_all() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+        File::create(path.join(".hidden")).unwrap();
+        File::create(path.join("shown.txt")).unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+        assert_eq!(child_names(&tree), vec!["shown.txt"]);
+
+        let config = Config {
+            root: path.to_path_buf(),
+            all: true,
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+        assert_eq!(child_names(&tree), vec![".hidden", "shown.txt"]);
+    }
+
+    #[test]
+    fn dry_run_filters_annotates_excluded_entries() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+        File::create(path.join(".hidden")).unwrap();
+        File::create(path.join("skipped.log")).unwrap();
+        File::create(path.join("shown.txt")).unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            dry_run_filters: true,
+            ignore_patterns: vec!["*.log".to_string()],
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+
+        assert_eq!(
+            child_names(&tree),
+            vec![".hidden", "shown.txt", "skipped.log"]
+        );
+        let hidden = tree.children.iter().find(|c| c.name == ".hidden").unwrap();
+        assert_eq!(hidden.note.as_deref(), Some("[excluded: hidden]"));
+        let skipped = tree.children.iter().find(|c| c.name == "skipped.log").unwrap();
+        assert_eq!(
+            skipped.note.as_deref(),
+            Some("[excluded: ignore pattern '*.log']")
+        );
+        let shown = tree.children.iter().find(|c| c.name == "shown.txt").unwrap();
+        assert_eq!(shown.note, None);
+    }
+
+    #[test]
+    fn root_label_overrides_displayed_root_name() {
+        let dir = tempdir().unwrap();
+        File::create(dir.path().join("a.txt")).unwrap();
+
+        let config = Config {
+            root: dir.path().to_path_buf(),
+            root_label: Some("Project".to_string()),
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+
+        assert_eq!(tree.name, "Project");
+        assert_eq!(child_names(&tree), vec!["a.txt"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn include_target_metadata_reports_target_size_for_symlinks() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        write_file(&path.join("target.txt"), 123);
+        std::os::unix::fs::symlink(path.join("target.txt"), path.join("link.txt")).unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            include_target_metadata: true,
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+
+        let link = tree.children.iter().find(|c| c.name == "link.txt").unwrap();
+        assert_eq!(link.kind, EntryKind::Symlink);
+        assert_eq!(link.size, Some(123));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn include_target_metadata_marks_broken_links() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        std::os::unix::fs::symlink(path.join("missing.txt"), path.join("dangling")).unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            include_target_metadata: true,
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+
+        let link = tree.children.iter().find(|c| c.name == "dangling").unwrap();
+        assert_eq!(link.note.as_deref(), Some("[broken link]"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follow_only_dirs_descends_dir_links_but_not_file_links() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        fs::create_dir(path.join("sub")).unwrap();
+        File::create(path.join("sub/inner.txt")).unwrap();
+        File::create(path.join("a.txt")).unwrap();
+        std::os::unix::fs::symlink(path.join("sub"), path.join("dirlink")).unwrap();
+        std::os::unix::fs::symlink(path.join("a.txt"), path.join("filelink")).unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            follow_only_dirs: true,
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+
+        let dirlink = tree.children.iter().find(|c| c.name == "dirlink").unwrap();
+        assert_eq!(dirlink.kind, EntryKind::Dir);
+        assert_eq!(child_names(dirlink), vec!["inner.txt"]);
+
+        let filelink = tree.children.iter().find(|c| c.name == "filelink").unwrap();
+        assert_eq!(filelink.kind, EntryKind::Symlink);
+        assert!(filelink.children.is_empty());
+        let note = filelink.note.as_deref().unwrap();
+        assert!(note.starts_with("-> "));
+        assert!(note.ends_with("a.txt"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follow_only_dirs_detects_cycles() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        std::os::unix::fs::symlink(path, path.join("loop")).unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            follow_only_dirs: true,
+            ..Config::default()
+        };
+        let outcome = walk(&config).unwrap();
+
+        let link = outcome.root.children.iter().find(|c| c.name == "loop").unwrap();
+        assert_eq!(link.note.as_deref(), Some("[cycle]"));
+        assert!(link.children.is_empty());
+        // 既定ではループはエラー要約にも載る
+        assert!(outcome
+            .errors
+            .iter()
+            .any(|(_, msg)| msg.contains("symlink loop")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn silent_loops_suppresses_cycle_marker() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        std::os::unix::fs::symlink(path, path.join("loop")).unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            follow_only_dirs: true,
+            silent_loops: true,
+            ..Config::default()
+        };
+        let outcome = walk(&config).unwrap();
+
+        let link = outcome.root.children.iter().find(|c| c.name == "loop").unwrap();
+        assert_eq!(link.note, None);
+        assert!(link.children.is_empty());
+        assert!(outcome.errors.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn walk_marks_symlinks_without_following() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        File::create(path.join("real.txt")).unwrap();
+        std::os::unix::fs::symlink(path.join("real.txt"), path.join("link.txt")).unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+
+        let link = tree.children.iter().find(|c| c.name == "link.txt").unwrap();
+        assert_eq!(link.kind, EntryKind::Symlink);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn prune_types_symlinks_only_keeps_structure() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        File::create(path.join("plain.txt")).unwrap();
+        fs::create_dir(path.join("sub")).unwrap();
+        std::os::unix::fs::symlink(path.join("plain.txt"), path.join("sub/link.txt")).unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            ..Config::default()
+        };
+        let mut tree = walk(&config).unwrap().root;
+        prune_types(&mut tree, &[EntryKind::Symlink]);
+
+        assert_eq!(child_names(&tree), vec!["sub"]);
+        assert_eq!(child_names(&tree.children[0]), vec!["link.txt"]);
+    }
+
+    #[test]
+    fn prune_types_files_only_drops_empty_dirs() {
+        let mut tree = dir_node(
+            ".",
+            vec![
+                file_node("a.txt"),
+                dir_node("empty", vec![]),
+                dir_node("sub", vec![file_node("b.txt")]),
+            ],
+        );
+
+        prune_types(&mut tree, &[EntryKind::File]);
+
+        assert_eq!(child_names(&tree), vec!["a.txt", "sub"]);
+    }
+
+    #[test]
+    fn newer_than_keeps_only_files_touched_after_reference() {
+        use std::time::{Duration, SystemTime};
+
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        let now = SystemTime::now();
+        let set_mtime = |name: &str, time: SystemTime| {
+            let file = File::create(path.join(name)).unwrap();
+            file.set_modified(time).unwrap();
+        };
+        set_mtime("old.txt", now - Duration::from_secs(3600));
+        set_mtime("reference.txt", now - Duration::from_secs(1800));
+        set_mtime("new.txt", now);
+
+        let mut config = Config {
+            root: path.to_path_buf(),
+            newer_than: Some(path.join("reference.txt")),
+            ignore_patterns: vec!["reference.txt".to_string()],
+            ..Config::default()
+        };
+        config.resolve_time_filters().unwrap();
+        let tree = walk(&config).unwrap().root;
+
+        assert_eq!(child_names(&tree), vec!["new.txt"]);
+    }
+
+    #[test]
+    fn older_than_keeps_only_files_before_reference() {
+        use std::time::{Duration, SystemTime};
+
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        let now = SystemTime::now();
+        let set_mtime = |name: &str, time: SystemTime| {
+            let file = File::create(path.join(name)).unwrap();
+            file.set_modified(time).unwrap();
+        };
+        set_mtime("old.txt", now - Duration::from_secs(3600));
+        set_mtime("reference.txt", now - Duration::from_secs(1800));
+        set_mtime("new.txt", now);
+
+        let mut config = Config {
+            root: path.to_path_buf(),
+            older_than: Some(path.join("reference.txt")),
+            ignore_patterns: vec!["reference.txt".to_string()],
+            ..Config::default()
+        };
+        config.resolve_time_filters().unwrap();
+        let tree = walk(&config).unwrap().root;
+
+        assert_eq!(child_names(&tree), vec!["old.txt"]);
+    }
+
+    #[test]
+    fn progress_event_is_valid_json_line() {
+        let event = progress_event(100, 2);
+
+        assert_eq!(event, "{\"scanned\":100,\"depth\":2}");
+        assert!(event.starts_with('{') && event.ends_with('}'));
+    }
+
+    #[test]
+    fn walk_clean_tree_collects_no_errors() {
+        let dir = tempdir().unwrap();
+        File::create(dir.path().join("a.txt")).unwrap();
+
+        let config = Config {
+            root: dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        let outcome = walk(&config).unwrap();
+
+        assert!(outcome.errors.is_empty());
+    }
+
+    #[test]
+    fn format_error_summary_lists_each_path() {
+        let errors = vec![
+            (PathBuf::from("/a/locked"), "permission denied".to_string()),
+            (PathBuf::from("/b/gone"), "path not found".to_string()),
+        ];
+
+        let summary = format_error_summary(&errors);
+        let lines: Vec<_> = summary.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "Skipped 2 paths due to errors:");
+        assert!(lines[1].contains("/a/locked"));
+        assert!(lines[2].contains("/b/gone"));
+    }
+
+    #[test]
+    fn descendant_count_counts_nested_entries() {
+        let tree = dir_node(
+            ".",
+            vec![
+                file_node("a.txt"),
+                dir_node("sub", vec![file_node("b.txt"), file_node("c.txt")]),
+            ],
+        );
+
+        assert_eq!(descendant_count(&tree), 4);
+    }
+
+    #[test]
+    fn prune_min_depth_hides_shallow_files_keeps_deep() {
+        let mut tree = dir_node(
+            ".",
+            vec![
+                file_node("top.txt"),
+                dir_node("sub", vec![file_node("deep.txt")]),
+                dir_node("shallow_only", vec![]),
+            ],
+        );
+
+        prune_min_depth(&mut tree, 2);
+
+        assert_eq!(child_names(&tree), vec!["sub"]);
+        assert_eq!(child_names(&tree.children[0]), vec!["deep.txt"]);
+    }
+
+    #[test]
+    fn collect_at_min_depth_lists_relative_paths() {
+        let tree = dir_node(
+            ".",
+            vec![
+                file_node("top.txt"),
+                dir_node("sub", vec![file_node("deep.txt")]),
+            ],
+        );
+
+        let paths = collect_at_min_depth(&tree, 2);
+        assert_eq!(paths, vec!["sub/deep.txt"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exec_per_entry_runs_once_per_entry_with_path() {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let mut root = dir_node("root", vec![file_node("a.txt"), file_node("b.txt")]);
+        root.path = PathBuf::from("root");
+        root.children[0].path = PathBuf::from("root/a.txt");
+        root.children[1].path = PathBuf::from("root/b.txt");
+
+        let cmd = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            format!("echo \"$0\" >> {}", log.display()),
+            "{}".to_string(),
+        ];
+        exec_per_entry(&root, &cmd);
+        let _ = std::io::stdout().flush();
+
+        let lines = fs::read_to_string(&log).unwrap();
+        let lines: Vec<&str> = lines.lines().collect();
+        assert_eq!(lines, ["root", "root/a.txt", "root/b.txt"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exec_batched_passes_all_paths_to_one_invocation() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let mut root = dir_node("root", vec![file_node("a.txt"), file_node("b.txt")]);
+        root.path = PathBuf::from("root");
+        root.children[0].path = PathBuf::from("root/a.txt");
+        root.children[1].path = PathBuf::from("root/b.txt");
+
+        let cmd = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            format!("echo \"$#:$@\" >> {}", log.display()),
+            "argv0".to_string(),
+            "{}".to_string(),
+        ];
+        exec_batched(&root, &cmd);
+
+        let lines = fs::read_to_string(&log).unwrap();
+        let lines: Vec<&str> = lines.lines().collect();
+        assert_eq!(lines, ["3:root root/a.txt root/b.txt"]);
+    }
+
+    #[test]
+    fn deduplicate_subtrees_collapses_identical_sibling_dirs() {
+        let mut tree = dir_node(
+            "root",
+            vec![
+                dir_node("a", vec![file_node("x.txt"), file_node("y.txt")]),
+                dir_node("b", vec![file_node("x.txt"), file_node("y.txt")]),
+                dir_node("c", vec![file_node("z.txt")]),
+            ],
+        );
+        tree.children[0].path = PathBuf::from("root/a");
+        deduplicate_subtrees(&mut tree);
+
+        assert_eq!(child_names(&tree.children[0]), ["x.txt", "y.txt"]);
+        assert!(tree.children[1].children.is_empty());
+        assert_eq!(
+            tree.children[1].note.as_deref(),
+            Some("[identical to root/a]")
+        );
+        assert_eq!(tree.children[2].note, None);
+    }
+
+    #[test]
+    fn handle_entry_error_follows_on_error_policy() {
+        let path = PathBuf::from("root/locked");
+
+        // skip: 何も記録せず続行する
+        let config = Config {
+            on_error: crate::config::OnError::Skip,
+            ..Config::default()
+        };
+        let mut state = WalkState::default();
+        let err = AppError::PermissionDenied(path.clone());
+        assert!(!handle_entry_error(&config, &mut state, &path, err).unwrap());
+        assert!(state.errors.is_empty());
+
+        // warn (既定): 記録して続行する
+        let config = Config::default();
+        let mut state = WalkState::default();
+        let err = AppError::PermissionDenied(path.clone());
+        assert!(handle_entry_error(&config, &mut state, &path, err).unwrap());
+        assert_eq!(state.errors.len(), 1);
+
+        // abort: エラーを伝播して打ち切る
+        let config = Config {
+            on_error: crate::config::OnError::Abort,
+            ..Config::default()
+        };
+        let mut state = WalkState::default();
+        let err = AppError::PermissionDenied(path.clone());
+        assert!(handle_entry_error(&config, &mut state, &path, err).is_err());
+        assert!(state.errors.is_empty());
+    }
+
+    #[test]
+    fn dir_semaphore_blocks_until_permit_released() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let semaphore = Arc::new(DirSemaphore::new(1));
+        let permit = semaphore.acquire();
+
+        let entered = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let semaphore = Arc::clone(&semaphore);
+            let entered = Arc::clone(&entered);
+            std::thread::spawn(move || {
+                let _permit = semaphore.acquire();
+                entered.store(true, Ordering::SeqCst);
+            })
+        };
+
+        // 許可を保持している間、もう一方の取得は待たされる
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!entered.load(Ordering::SeqCst));
+
+        drop(permit);
+        handle.join().unwrap();
+        assert!(entered.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn walk_show_branch_appends_branch_to_root_line() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join(".git")).unwrap();
+        std::fs::write(dir.path().join(".git/HEAD"), "ref: refs/heads/feature\n").unwrap();
+
+        let config = Config {
+            root: dir.path().to_path_buf(),
+            show_branch: true,
+            all: true,
+            ..Config::default()
+        };
+        let outcome = walk(&config).unwrap();
+        assert!(outcome.root.name.ends_with("(feature)"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn walk_verify_utf8_reports_invalid_names() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        write_file(&dir.path().join("ok.txt"), 1);
+        write_file(&dir.path().join(OsStr::from_bytes(b"bad\xff.txt")), 1);
+
+        let config = Config {
+            root: dir.path().to_path_buf(),
+            verify_utf8: true,
+            ..Config::default()
+        };
+        match walk(&config) {
+            Err(AppError::InvalidUtf8(paths)) => assert_eq!(paths.len(), 1),
+            other => panic!("expected InvalidUtf8, got {:?}", other),
+        }
+
+        // フラグなしでは従来どおり損失変換して続行する
+        let config = Config {
+            root: dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        assert_eq!(walk(&config).unwrap().root.children.len(), 2);
+    }
+
+    #[test]
+    fn walk_fetches_metadata_once_per_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(&dir.path().join("a.txt"), 1);
+        write_file(&dir.path().join("b.txt"), 1);
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        write_file(&dir.path().join("sub/c.txt"), 1);
+
+        // 注釈フラグを全部立ててもエントリあたり 1 回のまま
+        let config = Config {
+            root: dir.path().to_path_buf(),
+            show_size: true,
+            show_time: true,
+            du: true,
+            ..Config::default()
+        };
+        let outcome = walk(&config).unwrap();
+        assert_eq!(outcome.stat_calls, 4);
+    }
+
+    #[test]
+    fn walk_match_patterns_keep_only_matching_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(&dir.path().join("keep.rs"), 1);
+        write_file(&dir.path().join("drop.txt"), 1);
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+
+        let config = Config {
+            root: dir.path().to_path_buf(),
+            match_patterns: vec!["*.rs".to_string()],
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+        // ディレクトリはパターンに関わらず残る
+        assert_eq!(child_names(&tree), ["keep.rs", "sub"]);
+    }
+
+    #[test]
+    fn auto_max_depth_picks_shallower_limit_for_wide_trees() {
+        let wide = tempfile::tempdir().unwrap();
+        for i in 0..20 {
+            write_file(&wide.path().join(format!("f{}.txt", i)), 1);
+        }
+
+        let narrow = tempfile::tempdir().unwrap();
+        let mut dir = narrow.path().to_path_buf();
+        for i in 0..5 {
+            dir = dir.join(format!("level{}", i));
+            std::fs::create_dir(&dir).unwrap();
+        }
+
+        let wide_depth = auto_max_depth(wide.path(), 10);
+        let narrow_depth = auto_max_depth(narrow.path(), 10);
+        assert_eq!(wide_depth, 1);
+        assert!(narrow_depth > wide_depth);
+    }
+
+    #[test]
+    fn quiet_permission_renders_unreadable_dir_as_empty() {
+        let path = PathBuf::from("root/locked");
+
+        let config = Config {
+            quiet_permission: true,
+            ..Config::default()
+        };
+        let mut state = WalkState::default();
+        let err = AppError::PermissionDenied(path.clone());
+        let children = dir_error_children(&config, &mut state, &path, err).unwrap();
+        assert!(children.is_empty());
+        assert!(state.errors.is_empty());
+
+        // フラグなしでは従来どおりマーカーを出す
+        let config = Config::default();
+        let mut state = WalkState::default();
+        let err = AppError::PermissionDenied(path.clone());
+        let children = dir_error_children(&config, &mut state, &path, err).unwrap();
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].kind, EntryKind::Marker);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn walk_xattr_lists_attribute_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("tagged.txt");
+        write_file(&file, 1);
+        // tmpfs 等で user xattr が使えない環境ではスキップする
+        if xattr::set(&file, "user.test", b"1").is_err() {
+            return;
+        }
+
+        let config = Config {
+            root: dir.path().to_path_buf(),
+            xattr: true,
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+        assert_eq!(tree.children[0].note.as_deref(), Some("[xattr: user.test]"));
+    }
+
+    #[test]
+    fn collapse_files_summarizes_files_but_expands_subdirs() {
+        let mut tree = dir_node(
+            "root",
+            vec![
+                file_node("a.txt"),
+                file_node("b.txt"),
+                dir_node("sub", vec![file_node("c.txt")]),
+            ],
+        );
+        collapse_files(&mut tree);
+
+        assert_eq!(child_names(&tree), ["sub", "2 files"]);
+        assert_eq!(tree.children[1].kind, EntryKind::Marker);
+        assert_eq!(child_names(&tree.children[0]), ["1 file"]);
+    }
+
+    #[test]
+    fn flatten_tree_lists_relative_paths_in_walk_order() {
+        let tree = dir_node(
+            ".",
+            vec![
+                file_node("top.txt"),
+                dir_node("sub", vec![file_node("deep.txt")]),
+            ],
+        );
+        let paths: Vec<String> = flatten_tree(&tree).into_iter().map(|(p, _)| p).collect();
+        assert_eq!(paths, ["top.txt", "sub", "sub/deep.txt"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn on_symlink_show_annotates_target_without_following() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        fs::create_dir(path.join("sub")).unwrap();
+        File::create(path.join("sub/inner.txt")).unwrap();
+        std::os::unix::fs::symlink(path.join("sub"), path.join("dirlink")).unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            on_symlink: Some(SymlinkPolicy::Show),
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+
+        let link = tree.children.iter().find(|c| c.name == "dirlink").unwrap();
+        assert_eq!(link.kind, EntryKind::Symlink);
+        assert!(link.children.is_empty());
+        assert!(link.note.as_deref().unwrap().starts_with("-> "));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn on_symlink_follow_descends_dir_links_with_cycle_detection() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        fs::create_dir(path.join("sub")).unwrap();
+        File::create(path.join("sub/inner.txt")).unwrap();
+        std::os::unix::fs::symlink(path.join("sub"), path.join("dirlink")).unwrap();
+        std::os::unix::fs::symlink(path, path.join("loop")).unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            on_symlink: Some(SymlinkPolicy::Follow),
+            ..Config::default()
+        };
+        let outcome = walk(&config).unwrap();
+
+        let link = outcome.root.children.iter().find(|c| c.name == "dirlink").unwrap();
+        assert_eq!(link.kind, EntryKind::Dir);
+        assert_eq!(link.children[0].name, "inner.txt");
+        assert!(outcome
+            .errors
+            .iter()
+            .any(|(_, msg)| msg.contains("symlink loop")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn on_symlink_skip_omits_links_entirely() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        fs::create_dir(path.join("sub")).unwrap();
+        File::create(path.join("a.txt")).unwrap();
+        std::os::unix::fs::symlink(path.join("sub"), path.join("dirlink")).unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            on_symlink: Some(SymlinkPolicy::Skip),
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+
+        assert!(tree.children.iter().all(|c| c.name != "dirlink"));
+        assert_eq!(tree.children.len(), 2);
+    }
+
+    #[test]
+    fn skip_names_excludes_exact_matches_only() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        File::create(path.join("secret.txt")).unwrap();
+        File::create(path.join("secret.txt.bak")).unwrap();
+        File::create(path.join("other.txt")).unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            skip_names: ["secret.txt".to_string()].into_iter().collect(),
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+
+        let names: Vec<&str> = tree.children.iter().map(|c| c.name.as_str()).collect();
+        assert!(!names.contains(&"secret.txt"));
+        assert!(names.contains(&"secret.txt.bak"));
+        assert!(names.contains(&"other.txt"));
+    }
+
+    #[test]
+    fn fold_extensions_collapses_groups_over_threshold() {
+        let mut files: Vec<Node> = (0..20).map(|i| file_node(&format!("img{:02}.png", i))).collect();
+        files.push(file_node("readme.md"));
+        files.push(file_node("Makefile"));
+        let mut tree = dir_node(".", files);
+
+        fold_extensions(&mut tree, 5);
+
+        let names: Vec<&str> = tree.children.iter().map(|c| c.name.as_str()).collect();
+        assert!(names.contains(&"*.png (20 files)"));
+        assert!(names.contains(&"readme.md"));
+        assert!(names.contains(&"Makefile"));
+        assert!(!names.iter().any(|n| n.ends_with(".png") && !n.starts_with('*')));
+    }
+
+    #[test]
+    fn prune_files_below_hides_deep_files_but_keeps_dirs() {
+        let mut tree = dir_node(
+            ".",
+            vec![
+                file_node("top.txt"),
+                dir_node(
+                    "sub",
+                    vec![file_node("mid.txt"), dir_node("deeper", vec![file_node("bottom.txt")])],
+                ),
+            ],
+        );
+
+        prune_files_below(&mut tree, 1, 0);
+
+        let names: Vec<&str> = tree.children.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["top.txt", "sub"]);
+        let sub = &tree.children[1];
+        assert_eq!(sub.children.len(), 1);
+        assert_eq!(sub.children[0].name, "deeper");
+        assert!(sub.children[0].children.is_empty());
+    }
+
+    #[test]
+    fn fast_mode_classifies_dirs_and_files_without_stat() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+
+        fs::create_dir(path.join("sub")).unwrap();
+        File::create(path.join("sub/inner.txt")).unwrap();
+        File::create(path.join("a.txt")).unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            fast: true,
+            ..Config::default()
+        };
+        let outcome = walk(&config).unwrap();
+
+        let sub = outcome.root.children.iter().find(|c| c.name == "sub").unwrap();
+        assert_eq!(sub.kind, EntryKind::Dir);
+        assert_eq!(sub.children[0].name, "inner.txt");
+        let file = outcome.root.children.iter().find(|c| c.name == "a.txt").unwrap();
+        assert_eq!(file.kind, EntryKind::File);
+        assert_eq!(file.size, None);
+        // file_type で分類できたエントリには stat していない
+        assert_eq!(outcome.stat_calls, 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn relative_targets_rewrites_internal_links_only() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+        let outside = tempdir().unwrap();
+        let external = outside.path().join("ext.txt");
+
+        fs::create_dir(path.join("sub")).unwrap();
+        File::create(path.join("sub/target.txt")).unwrap();
+        File::create(&external).unwrap();
+        std::os::unix::fs::symlink(path.join("sub/target.txt"), path.join("internal")).unwrap();
+        std::os::unix::fs::symlink(&external, path.join("external")).unwrap();
+
+        let config = Config {
+            root: path.to_path_buf(),
+            on_symlink: Some(SymlinkPolicy::Show),
+            relative_targets: true,
+            ..Config::default()
+        };
+        let tree = walk(&config).unwrap().root;
+
+        let internal = tree.children.iter().find(|c| c.name == "internal").unwrap();
+        assert_eq!(internal.note.as_deref(), Some("-> sub/target.txt"));
+        let ext = tree.children.iter().find(|c| c.name == "external").unwrap();
+        assert!(ext.note.as_deref().unwrap().starts_with("-> /"));
+    }
+
+    #[test]
+    fn allow_file_walks_regular_file_as_single_node() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("single.txt");
+        fs::write(&path, b"hello").unwrap();
+
+        let config = Config {
+            root: path.clone(),
+            allow_file: true,
+            ..Config::default()
+        };
+        let outcome = walk(&config).unwrap();
+
+        assert_eq!(outcome.root.kind, EntryKind::File);
+        assert_eq!(outcome.root.size, Some(5));
+        assert!(outcome.root.children.is_empty());
+        assert_eq!(outcome.root.name, path.display().to_string());
+    }
+}